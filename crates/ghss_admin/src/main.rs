@@ -0,0 +1,181 @@
+use argh::FromArgs;
+use chrono::{DateTime, TimeZone, Utc};
+use ghss_store_client::{
+    connect_channel, AuditLogRequest, Credentials, DeleteRepositoryRequest, PurgeRepositoryRequest,
+    Scope, StoreClient, TlsConfig,
+};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Administrative actions on the data in the store. Every action is recorded
+/// in the store's audit log. Calls are authenticated with env
+/// STORE_TOKEN_SECRET and use TLS if env STORE_TLS_CA_PATH is set.
+#[derive(FromArgs)]
+struct Args {
+    /// URL of the store. Defaults to env STORE_URL or http://localhost:50051.
+    #[argh(option, default = "default_store_url()")]
+    store_url: String,
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Delete(DeleteCommand),
+    Purge(PurgeCommand),
+    AuditLog(AuditLogCommand),
+}
+
+/// Delete all data of a repository.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "delete")]
+struct DeleteCommand {
+    /// store ID of the repository, i.e. the GitHub repository ID, prefixed
+    /// with the instance name for GitHub Enterprise
+    #[argh(positional)]
+    repository_id: String,
+    /// why the data is deleted, e.g. a ticket number
+    #[argh(option)]
+    reason: String,
+    /// who requested the deletion. Defaults to env USER.
+    #[argh(option, default = "default_requested_by()")]
+    requested_by: String,
+}
+
+/// Delete the builds, commits and hooks of a repository in a time range.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "purge")]
+struct PurgeCommand {
+    /// store ID of the repository
+    #[argh(positional)]
+    repository_id: String,
+    /// start of the time range (inclusive) in RFC 3339, e.g. 2020-01-01T00:00:00Z
+    #[argh(option, from_str_fn(parse_time))]
+    since: DateTime<Utc>,
+    /// end of the time range (exclusive) in RFC 3339
+    #[argh(option, from_str_fn(parse_time))]
+    until: DateTime<Utc>,
+    /// why the data is deleted, e.g. a ticket number
+    #[argh(option)]
+    reason: String,
+    /// who requested the deletion. Defaults to env USER.
+    #[argh(option, default = "default_requested_by()")]
+    requested_by: String,
+}
+
+/// Show the most recent entries of the audit log.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "audit-log")]
+struct AuditLogCommand {
+    /// maximum number of entries
+    #[argh(option, default = "100")]
+    limit: u32,
+}
+
+fn default_store_url() -> String {
+    std::env::var("STORE_URL").unwrap_or_else(|_| "http://localhost:50051".to_owned())
+}
+
+fn default_requested_by() -> String {
+    std::env::var("USER").unwrap_or_default()
+}
+
+fn load_store_tls() -> Option<TlsConfig> {
+    std::env::var("STORE_TLS_CA_PATH")
+        .ok()
+        .map(|ca_certificate_path| TlsConfig {
+            ca_certificate_path,
+            identity: std::env::var("STORE_TLS_CERTIFICATE_PATH")
+                .ok()
+                .map(|certificate_path| {
+                    let key_path = std::env::var("STORE_TLS_KEY_PATH")
+                        .unwrap_or_else(|_| panic!("env STORE_TLS_KEY_PATH"));
+                    (certificate_path, key_path)
+                }),
+        })
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|err| err.to_string())
+}
+
+fn format_time(timestamp: i64) -> String {
+    Utc.timestamp_millis(timestamp).to_rfc3339()
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let args: Args = argh::from_env();
+    let channel = connect_channel(args.store_url, load_store_tls().as_ref()).await?;
+    let mut client = StoreClient::new(channel);
+    if let Ok(secret) = std::env::var("STORE_TOKEN_SECRET") {
+        client = client.with_credentials(Credentials::new(
+            secret.as_bytes(),
+            "admin",
+            &[Scope::Admin],
+        ));
+    }
+
+    match args.command {
+        Command::Delete(command) => {
+            client
+                .delete_repository(DeleteRepositoryRequest {
+                    repository_id: command.repository_id.clone(),
+                    reason: command.reason,
+                    requested_by: command.requested_by,
+                })
+                .await?;
+            println!("Deleted repository {}", command.repository_id);
+        }
+        Command::Purge(command) => {
+            let reply = client
+                .purge_repository(PurgeRepositoryRequest {
+                    repository_id: command.repository_id.clone(),
+                    since: command.since.timestamp_millis(),
+                    until: command.until.timestamp_millis(),
+                    reason: command.reason,
+                    requested_by: command.requested_by,
+                })
+                .await?
+                .into_inner();
+            println!(
+                "Purged {} builds, {} commits and {} hooks of repository {}",
+                reply.builds, reply.commits, reply.hooks, command.repository_id
+            );
+        }
+        Command::AuditLog(command) => {
+            let reply = client
+                .get_audit_log(AuditLogRequest {
+                    limit: command.limit,
+                })
+                .await?
+                .into_inner();
+            for entry in reply.entries {
+                let range = if entry.action == "purge" {
+                    format!(
+                        " [{}, {})",
+                        format_time(entry.since),
+                        format_time(entry.until)
+                    )
+                } else {
+                    String::new()
+                };
+                println!(
+                    "{}\t{}\t{}{}\t{}\t{}\t{}",
+                    format_time(entry.timestamp),
+                    entry.action,
+                    entry.repository_id,
+                    range,
+                    entry.requested_by,
+                    entry.reason,
+                    entry.result
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use crate::{
+    check_run_to_build, millis_between, statuses_to_builds, workflow_job_name,
+    workflow_job_to_build, workflow_run_to_build,
+};
+use chrono::{DateTime, FixedOffset};
+use futures::stream::{self, Stream, StreamExt};
+use ghss_github::{CheckRun, CheckSuite, Client, MostRecentCommit};
+use ghss_store_client::{Build, Commit, HookedCommit};
+use itertools::Itertools;
+use std::convert::TryInto;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+fn check_runs_to_builds(check_runs: Vec<CheckRun>, check_suites: &[CheckSuite]) -> Vec<Build> {
+    check_runs
+        .into_iter()
+        .map(|mut check_run| {
+            // The check suite creation time is only included in webhook
+            // payloads, so look it up in the check suites of the commit.
+            if let Some(check_suite) = &mut check_run.check_suite {
+                if check_suite.created_at.is_none() {
+                    check_suite.created_at = check_suites
+                        .iter()
+                        .find(|suite| suite.id == check_suite.id)
+                        .map(|suite| suite.created_at);
+                }
+            }
+            check_run_to_build(check_run)
+        })
+        .collect()
+}
+
+fn is_forbidden_or_missing(err: &BoxError) -> bool {
+    match err
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|err| err.status())
+    {
+        Some(status) => {
+            status == reqwest::StatusCode::FORBIDDEN || status == reqwest::StatusCode::NOT_FOUND
+        }
+        None => false,
+    }
+}
+
+/// Returns the builds of the workflow runs of a commit and of their jobs.
+/// Installations that didn't grant the `actions` permission yield no builds.
+async fn get_workflow_builds(
+    client: &Client,
+    owner: &str,
+    repo: &str,
+    commit_sha: &str,
+) -> Result<(Vec<Build>, Vec<Build>), BoxError> {
+    let runs = match client.get_workflow_runs(owner, repo, commit_sha).await {
+        Ok(runs) => runs,
+        Err(err) if is_forbidden_or_missing(&err) => return Ok((Vec::new(), Vec::new())),
+        Err(err) => return Err(err),
+    };
+
+    let mut run_builds = Vec::new();
+    let mut job_builds = Vec::new();
+    for run in runs {
+        let jobs = client.get_workflow_run_jobs(owner, repo, run.id).await?;
+        let run_started_at = run
+            .run_started_at
+            .unwrap_or(run.created_at)
+            .timestamp_millis();
+        let first_job_started_at = jobs
+            .iter()
+            .filter(|job| job.run_attempt == run.run_attempt)
+            .map(|job| job.started_at.timestamp_millis())
+            .min();
+        let workflow_name = run.name.clone();
+        let event = run.event.clone();
+
+        let mut run_build = workflow_run_to_build(run);
+        run_build.queue_ms = first_job_started_at
+            .map(|started_at| millis_between(run_started_at, started_at))
+            .unwrap_or_default();
+
+        for job in jobs {
+            let mut job_build = workflow_job_to_build(job);
+            // Older API versions don't include the workflow name in jobs.
+            if job_build.workflow_name.is_empty() {
+                job_build.name = workflow_job_name(&workflow_name, &job_build.name);
+                job_build.workflow_name = workflow_name.clone();
+            }
+            if job_build.branch.is_empty() {
+                job_build.branch = run_build.branch.clone();
+            }
+            job_build.pull_request = run_build.pull_request;
+            job_build.event = event.clone();
+            job_builds.push(job_build);
+        }
+        run_builds.push(run_build);
+    }
+    Ok((run_builds, job_builds))
+}
+
+fn builds_to_commit(builds: Vec<&Build>, timestamp: DateTime<FixedOffset>) -> Commit {
+    let builds_len = builds.len().try_into().expect("convert build count");
+    let builds_successful = builds
+        .iter()
+        .filter(|build| build.successful)
+        .count()
+        .try_into()
+        .expect("convert successful build count");
+    let builds_failed = builds
+        .iter()
+        .filter(|build| build.failed)
+        .count()
+        .try_into()
+        .expect("convert failed build count");
+    let first = builds.first().expect("first build");
+    Commit {
+        build_name: first.name.clone(),
+        build_source: first.source,
+        commit: first.commit.clone(),
+        builds: builds_len,
+        builds_successful,
+        builds_failed,
+        timestamp: timestamp.timestamp_millis(),
+        branch: first.branch.clone(),
+        pull_request: first.pull_request,
+    }
+}
+
+fn builds_to_commits(builds: &[Build], committed_date: DateTime<FixedOffset>) -> Vec<Commit> {
+    (&builds.iter().group_by(|build| build.name.clone()))
+        .into_iter()
+        .map(|(_, group)| builds_to_commit(group.collect(), committed_date))
+        .collect()
+}
+
+fn set_branch(builds: &mut [Build], commit: &MostRecentCommit) {
+    for build in builds {
+        if !commit.branch.is_empty() {
+            build.branch = commit.branch.clone();
+        }
+        if commit.pull_request != 0 {
+            build.pull_request = commit.pull_request;
+        }
+    }
+}
+
+/// Looks up the commit dates of hooked commits, so their builds can be
+/// fetched.
+pub async fn get_hooked_commits(
+    client: &Client,
+    owner: &str,
+    repo: &str,
+    hooked_commits: Vec<HookedCommit>,
+) -> Result<Vec<MostRecentCommit>, BoxError> {
+    let commit_shas: Vec<String> = hooked_commits
+        .iter()
+        .map(|commit| commit.commit.clone())
+        .collect();
+    let commit_dates = client.get_commit_dates(owner, repo, &commit_shas).await?;
+    Ok(hooked_commits
+        .into_iter()
+        .zip(commit_dates.into_iter())
+        .map(|(commit, committed_date)| MostRecentCommit {
+            sha: commit.commit,
+            committed_date,
+            branch: commit.branch,
+            pull_request: commit.pull_request,
+        })
+        .collect())
+}
+
+/// Fetches the builds of the commits and yields them commit by commit, so
+/// they can be imported as they arrive.
+pub fn stream_builds<'a>(
+    client: &'a Client,
+    owner: &'a str,
+    repo: &'a str,
+    recent_commits: Vec<MostRecentCommit>,
+    max_concurrent_commits: usize,
+) -> impl Stream<Item = Result<(Vec<Build>, Vec<Commit>), BoxError>> + 'a {
+    stream::iter(recent_commits)
+        .map(move |commit| async move {
+            let (statuses, check_runs, check_suites, (run_builds, job_builds)) = futures::try_join!(
+                client.get_statuses(owner, repo, &commit.sha),
+                client.get_check_runs(owner, repo, &commit.sha),
+                client.get_check_suites(owner, repo, &commit.sha),
+                get_workflow_builds(client, owner, repo, &commit.sha),
+            )?;
+            let mut builds = Vec::new();
+            let mut commits = Vec::new();
+
+            let mut status_builds = statuses_to_builds(statuses, &commit.sha);
+            set_branch(&mut status_builds, &commit);
+            commits.extend(builds_to_commits(&status_builds, commit.committed_date));
+            builds.extend(status_builds);
+
+            let mut check_run_builds = check_runs_to_builds(check_runs, &check_suites);
+            set_branch(&mut check_run_builds, &commit);
+            commits.extend(builds_to_commits(&check_run_builds, commit.committed_date));
+            builds.extend(check_run_builds);
+
+            for mut workflow_builds in [run_builds, job_builds] {
+                workflow_builds.sort_by(|a, b| a.name.cmp(&b.name));
+                set_branch(&mut workflow_builds, &commit);
+                commits.extend(builds_to_commits(&workflow_builds, commit.committed_date));
+                builds.extend(workflow_builds);
+            }
+
+            Ok::<_, BoxError>((builds, commits))
+        })
+        .buffered(max_concurrent_commits)
+}
@@ -0,0 +1,277 @@
+use ghss_github::{
+    CheckRun, CheckRunConclusion, CommitStatus, CommitStatusState, WorkflowJob, WorkflowRun,
+    WorkflowStatus,
+};
+use ghss_store_client::{Build, BuildOutcome, BuildSource};
+use itertools::Itertools;
+use std::convert::TryInto;
+
+mod fetch;
+
+pub use fetch::{get_hooked_commits, stream_builds};
+
+fn is_successful(conclusion: &Option<CheckRunConclusion>) -> bool {
+    conclusion == &Some(CheckRunConclusion::Success)
+}
+
+fn is_failed(conclusion: &Option<CheckRunConclusion>) -> bool {
+    matches!(
+        conclusion,
+        Some(CheckRunConclusion::Failure)
+            | Some(CheckRunConclusion::TimedOut)
+            | Some(CheckRunConclusion::StartupFailure)
+    )
+}
+
+fn conclusion_outcome(conclusion: &Option<CheckRunConclusion>) -> BuildOutcome {
+    match conclusion {
+        None => BuildOutcome::Pending,
+        Some(CheckRunConclusion::Success) => BuildOutcome::Success,
+        Some(CheckRunConclusion::Failure) => BuildOutcome::Failure,
+        Some(CheckRunConclusion::Neutral) => BuildOutcome::Neutral,
+        Some(CheckRunConclusion::Cancelled) => BuildOutcome::Cancelled,
+        Some(CheckRunConclusion::TimedOut) => BuildOutcome::TimedOut,
+        Some(CheckRunConclusion::ActionRequired) => BuildOutcome::ActionRequired,
+        Some(CheckRunConclusion::Skipped) => BuildOutcome::Skipped,
+        Some(CheckRunConclusion::Stale) => BuildOutcome::Stale,
+        Some(CheckRunConclusion::StartupFailure) => BuildOutcome::StartupFailure,
+    }
+}
+
+fn status_outcome(state: &CommitStatusState) -> BuildOutcome {
+    match state {
+        CommitStatusState::Pending => BuildOutcome::Pending,
+        CommitStatusState::Error => BuildOutcome::Error,
+        CommitStatusState::Failure => BuildOutcome::Failure,
+        CommitStatusState::Success => BuildOutcome::Success,
+    }
+}
+
+// Saturates, as e.g. runs re-run long after their check suite was created
+// have waited longer than fits into u32.
+pub(crate) fn millis_between(start_millis: i64, end_millis: i64) -> u32 {
+    (end_millis - start_millis)
+        .max(0)
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+pub fn check_run_to_build(check_run: CheckRun) -> Build {
+    // Check runs are queued when their check suite is created.
+    let queued_at = check_run
+        .check_suite
+        .as_ref()
+        .and_then(|check_suite| check_suite.created_at)
+        .map(|created_at| created_at.timestamp_millis())
+        .unwrap_or_default();
+    Build {
+        name: check_run.name,
+        source: BuildSource::CheckRun as i32,
+        commit: check_run.head_sha,
+        successful: is_successful(&check_run.conclusion),
+        failed: is_failed(&check_run.conclusion),
+        outcome: conclusion_outcome(&check_run.conclusion) as i32,
+        duration_ms: match check_run.completed_at {
+            Some(completed_at) => millis_between(
+                check_run.started_at.timestamp_millis(),
+                completed_at.timestamp_millis(),
+            ),
+            None => 0,
+        },
+        timestamp: check_run.started_at.timestamp_millis(),
+        branch: check_run
+            .check_suite
+            .and_then(|check_suite| check_suite.head_branch)
+            .unwrap_or_default(),
+        pull_request: check_run
+            .pull_requests
+            .first()
+            .map(|pull_request| pull_request.number)
+            .unwrap_or_default(),
+        queue_ms: match queued_at {
+            0 => 0,
+            queued_at => millis_between(queued_at, check_run.started_at.timestamp_millis()),
+        },
+        queued_at,
+        ..Default::default()
+    }
+}
+
+pub fn workflow_run_to_build(run: WorkflowRun) -> Build {
+    let started_at = run.run_started_at.unwrap_or(run.created_at);
+    Build {
+        name: run.name.clone(),
+        source: BuildSource::WorkflowRun as i32,
+        commit: run.head_sha,
+        successful: is_successful(&run.conclusion),
+        failed: is_failed(&run.conclusion),
+        outcome: conclusion_outcome(&run.conclusion) as i32,
+        // Runs don't report a completion time, but they aren't updated
+        // anymore once completed.
+        duration_ms: match run.status {
+            WorkflowStatus::Completed => millis_between(
+                started_at.timestamp_millis(),
+                run.updated_at.timestamp_millis(),
+            ),
+            _ => 0,
+        },
+        timestamp: started_at.timestamp_millis(),
+        branch: run.head_branch.unwrap_or_default(),
+        pull_request: run
+            .pull_requests
+            .first()
+            .map(|pull_request| pull_request.number)
+            .unwrap_or_default(),
+        workflow_name: run.name,
+        event: run.event,
+        attempt: run.run_attempt,
+        // The run is queued when it starts, but it's only known how long
+        // it waited once the jobs have started.
+        queue_ms: 0,
+        queued_at: started_at.timestamp_millis(),
+    }
+}
+
+/// Returns the build name of a workflow job, which includes the name of its
+/// workflow, as jobs of different workflows often share names.
+pub fn workflow_job_name(workflow_name: &str, job_name: &str) -> String {
+    format!("{} / {}", workflow_name, job_name)
+}
+
+pub fn workflow_job_to_build(job: WorkflowJob) -> Build {
+    let workflow_name = job.workflow_name.unwrap_or_default();
+    Build {
+        name: if workflow_name.is_empty() {
+            job.name
+        } else {
+            workflow_job_name(&workflow_name, &job.name)
+        },
+        source: BuildSource::WorkflowJob as i32,
+        commit: job.head_sha,
+        successful: is_successful(&job.conclusion),
+        failed: is_failed(&job.conclusion),
+        outcome: conclusion_outcome(&job.conclusion) as i32,
+        duration_ms: match job.completed_at {
+            Some(completed_at) => millis_between(
+                job.started_at.timestamp_millis(),
+                completed_at.timestamp_millis(),
+            ),
+            None => 0,
+        },
+        timestamp: job.started_at.timestamp_millis(),
+        branch: job.head_branch.unwrap_or_default(),
+        workflow_name,
+        attempt: job.run_attempt,
+        queue_ms: match job.created_at {
+            Some(created_at) => millis_between(
+                created_at.timestamp_millis(),
+                job.started_at.timestamp_millis(),
+            ),
+            None => 0,
+        },
+        queued_at: job
+            .created_at
+            .map(|created_at| created_at.timestamp_millis())
+            .unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+fn statuses_to_build(statuses: Vec<CommitStatus>, commit: String) -> Build {
+    let mut iter = statuses.into_iter();
+    let first = iter.next().unwrap();
+    let first_millis = first.created_at.timestamp_millis();
+    let name = first.context.clone();
+    let last = iter.last().unwrap_or(first);
+    let last_millis = last.created_at.timestamp_millis();
+    Build {
+        name,
+        source: BuildSource::Status as i32,
+        commit,
+        successful: last.state == CommitStatusState::Success,
+        failed: last.state == CommitStatusState::Error || last.state == CommitStatusState::Failure,
+        outcome: status_outcome(&last.state) as i32,
+        duration_ms: millis_between(first_millis, last_millis),
+        timestamp: first_millis,
+        ..Default::default()
+    }
+}
+
+/// Reconstructs builds from the statuses of a commit. A build starts with the
+/// first status of a context and ends with the first status that isn't
+/// pending.
+pub fn statuses_to_builds(mut statuses: Vec<CommitStatus>, commit_sha: &str) -> Vec<Build> {
+    statuses.sort_by(|a, b| {
+        a.created_at
+            .timestamp_millis()
+            .cmp(&b.created_at.timestamp_millis())
+    });
+
+    statuses
+        .into_iter()
+        .group_by(|status| status.context.clone())
+        .into_iter()
+        .flat_map(|group| {
+            let (_, statuses) = group;
+            statuses
+                .batching(|it| match it.next() {
+                    None => None,
+                    Some(x) => {
+                        let mut result: Vec<CommitStatus> = vec![x];
+                        while result.last().unwrap().state == CommitStatusState::Pending {
+                            match it.next() {
+                                Some(x) => result.push(x),
+                                None => break,
+                            };
+                        }
+                        Some(result)
+                    }
+                })
+                .map(|statuses| statuses_to_build(statuses, commit_sha.to_owned()))
+                .collect_vec()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use ghss_github::{CheckRunCheckSuite, CheckRunOutput, CheckRunStatus};
+
+    #[test]
+    fn saturates_queue_time_of_old_check_suites() {
+        let time = |time| DateTime::parse_from_rfc3339(time).unwrap();
+        let check_run = CheckRun {
+            id: 1,
+            head_sha: "sha".into(),
+            node_id: String::new(),
+            external_id: String::new(),
+            url: String::new(),
+            html_url: String::new(),
+            details_url: String::new(),
+            status: CheckRunStatus::Completed,
+            conclusion: Some(CheckRunConclusion::Success),
+            started_at: time("2020-06-01T00:00:00Z"),
+            completed_at: Some(time("2020-06-01T00:01:00Z")),
+            output: CheckRunOutput {
+                title: None,
+                summary: None,
+                text: None,
+                annotations_count: 0,
+                annotations_url: String::new(),
+            },
+            name: "ci".into(),
+            check_suite: Some(CheckRunCheckSuite {
+                id: 1,
+                head_branch: None,
+                // Re-run more than 49.7 days after the suite was created.
+                created_at: Some(time("2020-01-01T00:00:00Z")),
+            }),
+            pull_requests: Vec::new(),
+        };
+        let build = check_run_to_build(check_run);
+        assert_eq!(build.queue_ms, u32::MAX);
+        assert_eq!(build.duration_ms, 60_000);
+    }
+}
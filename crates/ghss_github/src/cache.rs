@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Response of a successful GET request, which can be revalidated with a
+/// conditional request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Link header, which contains the URL of the next page.
+    pub link: Option<String>,
+    pub body: String,
+}
+
+/// Storage for API responses used by conditional requests. Entries are keyed
+/// by URL and media type, but not by user. A cache must not be shared between
+/// clients authenticated as different users.
+pub trait ResponseCache: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<CachedResponse>, BoxError>;
+    fn put(&self, key: &str, response: CachedResponse) -> Result<(), BoxError>;
+}
+
+#[derive(Default)]
+struct MemoryEntries {
+    responses: HashMap<String, (u64, CachedResponse)>,
+    // Keys by the time they were last used, least recently used first.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl MemoryEntries {
+    fn touch(&mut self, key: &str, used_at: u64) -> u64 {
+        self.recency.remove(&used_at);
+        self.clock += 1;
+        self.recency.insert(self.clock, key.to_owned());
+        self.clock
+    }
+}
+
+/// Keeps responses in memory. Once there are more than `max_entries`
+/// responses, the least recently used one is removed.
+pub struct MemoryCache {
+    max_entries: usize,
+    entries: Mutex<MemoryEntries>,
+}
+
+impl MemoryCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl ResponseCache for MemoryCache {
+    fn get(&self, key: &str) -> Result<Option<CachedResponse>, BoxError> {
+        let mut entries = self.entries.lock().map_err(|_| "memory cache poisoned")?;
+        let used_at = match entries.responses.get(key) {
+            Some((used_at, _)) => *used_at,
+            None => return Ok(None),
+        };
+        let used_at = entries.touch(key, used_at);
+        let entry = entries.responses.get_mut(key).unwrap();
+        entry.0 = used_at;
+        Ok(Some(entry.1.clone()))
+    }
+
+    fn put(&self, key: &str, response: CachedResponse) -> Result<(), BoxError> {
+        let mut entries = self.entries.lock().map_err(|_| "memory cache poisoned")?;
+        let used_at = entries
+            .responses
+            .get(key)
+            .map_or(0, |(used_at, _)| *used_at);
+        let used_at = entries.touch(key, used_at);
+        entries
+            .responses
+            .insert(key.to_owned(), (used_at, response));
+        while entries.responses.len() > self.max_entries {
+            let oldest = match entries.recency.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+            if let Some(key) = entries.recency.remove(&oldest) {
+                entries.responses.remove(&key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Stores every response as a JSON file in a directory. Once there are more
+/// than `max_entries` responses, the least recently written ones are removed.
+pub struct DiskCache {
+    directory: PathBuf,
+    max_entries: usize,
+    // Approximate number of responses in the directory.
+    entries: Mutex<usize>,
+}
+
+fn is_entry(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("json"))
+}
+
+impl DiskCache {
+    pub fn new<P: Into<PathBuf>>(directory: P, max_entries: usize) -> Result<Self, BoxError> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        let mut entries = 0;
+        for entry in std::fs::read_dir(&directory)? {
+            if is_entry(&entry?.path()) {
+                entries += 1;
+            }
+        }
+        Ok(Self {
+            directory,
+            max_entries,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory
+            .join(format!("{:x}.json", Sha256::digest(key.as_bytes())))
+    }
+
+    // Removes the least recently written responses, leaving room for a tenth
+    // of the maximum before the next eviction.
+    fn evict(&self, entries: &mut usize) -> Result<(), BoxError> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if is_entry(&entry.path()) {
+                files.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        files.sort();
+        let keep = self.max_entries - self.max_entries / 10;
+        let remove = files.len().saturating_sub(keep);
+        for (_, path) in &files[..remove] {
+            match std::fs::remove_file(path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        *entries = files.len() - remove;
+        Ok(())
+    }
+}
+
+impl ResponseCache for DiskCache {
+    fn get(&self, key: &str) -> Result<Option<CachedResponse>, BoxError> {
+        match std::fs::read(self.path(key)) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn put(&self, key: &str, response: CachedResponse) -> Result<(), BoxError> {
+        // Write to a temporary file first, so readers never see partial files.
+        // The name is unique, as the same response may be written
+        // concurrently.
+        let path = self.path(key);
+        let tmp_path = path.with_extension(format!("{:016x}.tmp", rand::random::<u64>()));
+        std::fs::write(&tmp_path, serde_json::to_vec(&response)?)?;
+        let is_new = !path.exists();
+        if let Err(err) = std::fs::rename(&tmp_path, path) {
+            let _ = std::fs::remove_file(tmp_path);
+            return Err(err.into());
+        }
+
+        if is_new {
+            let mut entries = self.entries.lock().map_err(|_| "disk cache poisoned")?;
+            *entries += 1;
+            if *entries > self.max_entries {
+                self.evict(&mut entries)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &str) -> CachedResponse {
+        CachedResponse {
+            etag: Some("\"etag\"".into()),
+            last_modified: None,
+            link: None,
+            body: body.into(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "ghss-cache-{}-{}-{:x}",
+            name,
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn evicts_least_recently_used_responses() {
+        let cache = MemoryCache::new(2);
+        cache.put("a", response("1")).unwrap();
+        cache.put("b", response("2")).unwrap();
+        assert!(cache.get("a").unwrap().is_some());
+        cache.put("c", response("3")).unwrap();
+        assert!(cache.get("b").unwrap().is_none());
+        assert_eq!(cache.get("a").unwrap().unwrap().body, "1");
+        assert_eq!(cache.get("c").unwrap().unwrap().body, "3");
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.recency.len(), 2);
+    }
+
+    #[test]
+    fn stores_responses() {
+        let directory = temp_dir("store");
+        let cache = DiskCache::new(&directory, 10).unwrap();
+        assert!(cache.get("a").unwrap().is_none());
+        cache.put("a", response("1")).unwrap();
+        cache.put("a", response("2")).unwrap();
+        assert_eq!(cache.get("a").unwrap().unwrap().body, "2");
+        assert_eq!(*cache.entries.lock().unwrap(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn evicts_oldest_responses() {
+        let directory = temp_dir("evict");
+        let cache = DiskCache::new(&directory, 10).unwrap();
+        for i in 0..11 {
+            cache.put(&i.to_string(), response("")).unwrap();
+            // Modification times need to differ to be ordered.
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(*cache.entries.lock().unwrap(), 9);
+        assert!(cache.get("0").unwrap().is_none());
+        assert!(cache.get("1").unwrap().is_none());
+        assert!(cache.get("10").unwrap().is_some());
+
+        let reopened = DiskCache::new(&directory, 10).unwrap();
+        assert_eq!(*reopened.entries.lock().unwrap(), 9);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,12 +1,183 @@
+use super::cache::{CachedResponse, ResponseCache};
+use super::models::RateLimit;
 use super::page_links;
-use opentelemetry::api::{Context, FutureExt, Key, SpanKind, StatusCode, TraceContextExt, Tracer};
-use reqwest::header::{ACCEPT, LINK};
-use reqwest::{Client, Request, Url};
+use opentelemetry::api::{
+    Context, FutureExt, Key, KeyValue, SpanKind, StatusCode, TraceContextExt, Tracer,
+};
+use rand::Rng;
+use reqwest::header::{
+    HeaderMap, HeaderValue, ACCEPT, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LINK,
+    RETRY_AFTER,
+};
+use reqwest::{Client, Method, Request, RequestBuilder, Url};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+const MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Longer waits for a rate limit reset fail the request instead.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(15 * 60);
+
+/// HTTP client, which keeps track of the GitHub rate limit budget and retries
+/// requests failing because of rate limits or transient errors.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    rate_limits: Arc<Mutex<HashMap<String, RateLimit>>>,
+    cache: Option<Arc<dyn ResponseCache>>,
+    requests: Option<Arc<Semaphore>>,
+}
+
+impl HttpClient {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            rate_limits: Arc::new(Mutex::new(HashMap::new())),
+            cache: None,
+            requests: None,
+        }
+    }
+
+    /// Limits the number of requests in flight to the permits of the
+    /// semaphore. The semaphore can be shared by multiple clients.
+    pub fn set_request_limit(&mut self, requests: Arc<Semaphore>) {
+        self.requests = Some(requests);
+    }
+
+    /// Revalidates GET requests with conditional requests and serves the
+    /// cached response if it wasn't modified.
+    pub fn set_cache(&mut self, cache: Arc<dyn ResponseCache>) {
+        self.cache = Some(cache);
+    }
+
+    pub fn get(&self, url: Url) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: Url) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    /// Returns the rate limit budget of the given resource (e.g. "core" or
+    /// "graphql") as of the most recent response.
+    pub fn rate_limit(&self, resource: &str) -> Option<RateLimit> {
+        self.rate_limits
+            .lock()
+            .expect("rate limits lock")
+            .get(resource)
+            .cloned()
+    }
+
+    fn update_rate_limit(&self, resource: &str, headers: &HeaderMap) -> Option<RateLimit> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let rate_limit = RateLimit {
+            limit: header("x-ratelimit-limit")?.parse().ok()?,
+            remaining: header("x-ratelimit-remaining")?.parse().ok()?,
+            reset: header("x-ratelimit-reset")?.parse().ok()?,
+        };
+        let resource = header("x-ratelimit-resource").unwrap_or(resource);
+        self.rate_limits
+            .lock()
+            .expect("rate limits lock")
+            .insert(resource.to_owned(), rate_limit.clone());
+        Some(rate_limit)
+    }
+}
+
+fn cache_key(request: &Request) -> String {
+    let accept = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    format!("{} {}", accept, request.url())
+}
+
+fn header_string(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+fn next_page_url(link: Option<&str>) -> Result<Option<Url>, BoxError> {
+    match link.and_then(|link| page_links::parse(link).next) {
+        Some(url) => Ok(Some(Url::parse(url)?)),
+        None => Ok(None),
+    }
+}
+
+// Rate limit resource of a request as reported by GitHub in the
+// x-ratelimit-resource header. Rate limits are tracked by this name.
+fn resource(url: &Url) -> &'static str {
+    // GitHub Enterprise Server serves the REST API under /api/v3.
+    let path = url.path();
+    let path = path.strip_prefix("/api/v3").unwrap_or(path);
+    if path.ends_with("/graphql") {
+        "graphql"
+    } else if path.starts_with("/search/") {
+        "search"
+    } else if path.starts_with("/app-manifests/") {
+        "integration_manifest"
+    } else {
+        "core"
+    }
+}
+
+fn until_reset(rate_limit: &RateLimit) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    // Add a second to be safe from clock skew.
+    Duration::from_secs((rate_limit.reset - now + 1).max(0) as u64)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    let max = INITIAL_BACKOFF
+        .checked_mul(1 << attempt.min(16))
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF);
+    let millis = max.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(millis / 2, millis + 1))
+}
+
+fn retry_delay(
+    status: reqwest::StatusCode,
+    headers: &HeaderMap,
+    rate_limit: Option<&RateLimit>,
+    attempt: u32,
+) -> Option<Duration> {
+    match status.as_u16() {
+        403 | 429 => {
+            if let Some(retry_after) = headers
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+            {
+                // Secondary rate limit
+                Some(Duration::from_secs(retry_after).min(MAX_RATE_LIMIT_WAIT))
+            } else if let Some(rate_limit) = rate_limit.filter(|r| r.remaining == 0) {
+                Some(until_reset(rate_limit)).filter(|wait| *wait <= MAX_RATE_LIMIT_WAIT)
+            } else if status.as_u16() == 429 {
+                Some(backoff(attempt))
+            } else {
+                // Most likely missing permissions
+                None
+            }
+        }
+        500 | 502 | 503 | 504 => Some(backoff(attempt)),
+        _ => None,
+    }
+}
+
 struct Response<T> {
     data: T,
     next_page_url: Option<Url>,
@@ -16,8 +187,8 @@ pub const MACHINE_MAN_PREVIEW: &str = "application/vnd.github.machine-man-previe
 pub const ANTIOPE_PREVIEW: &str = "application/vnd.github.antiope-preview+json";
 
 async fn call_api<T: DeserializeOwned>(
-    client: &Client,
-    request: Request,
+    client: &HttpClient,
+    mut request: Request,
 ) -> Result<Response<T>, BoxError> {
     let tracer = opentelemetry::global::tracer("github");
     let span = tracer
@@ -29,10 +200,115 @@ async fn call_api<T: DeserializeOwned>(
         ])
         .start(&tracer);
     let cx = Context::current_with_span(span);
+    let span = cx.span();
+    let resource = resource(request.url());
 
-    let res = client.execute(request).with_context(cx.clone()).await;
+    // Cache errors are not fatal. The request is sent without cache instead.
+    let cache = client
+        .cache
+        .as_ref()
+        .filter(|_| request.method() == Method::GET)
+        .map(|cache| (cache, cache_key(&request)));
+    let cached = match cache.as_ref().map(|(cache, key)| cache.get(key)) {
+        Some(Ok(cached)) => cached,
+        Some(Err(err)) => {
+            span.add_event(
+                "cache error".into(),
+                vec![Key::new("error").string(err.to_string())],
+            );
+            None
+        }
+        None => None,
+    };
+    if let Some(cached) = cached.as_ref() {
+        if let Some(etag) = cached.etag.as_deref() {
+            request
+                .headers_mut()
+                .insert(IF_NONE_MATCH, HeaderValue::from_str(etag)?);
+        }
+        if let Some(last_modified) = cached.last_modified.as_deref() {
+            request
+                .headers_mut()
+                .insert(IF_MODIFIED_SINCE, HeaderValue::from_str(last_modified)?);
+        }
+    }
+
+    let mut attempt = 0;
+    let res = loop {
+        // Don't send requests, which will certainly fail.
+        if let Some(rate_limit) = client
+            .rate_limit(resource)
+            .filter(|rate_limit| rate_limit.remaining == 0)
+        {
+            let wait = until_reset(&rate_limit);
+            if wait > MAX_RATE_LIMIT_WAIT {
+                let err = format!("{} rate limit exceeded for {}s", resource, wait.as_secs());
+                span.set_status(StatusCode::ResourceExhausted, err.clone());
+                return Err(err.into());
+            }
+            span.add_event(
+                "rate limit wait".into(),
+                vec![Key::new("wait_ms").u64(wait.as_millis() as u64)],
+            );
+            tokio::time::delay_for(wait).await;
+        }
+
+        let attempt_request = request
+            .try_clone()
+            .ok_or("request body does not support retries")?;
+        let permit = match client.requests.as_ref() {
+            Some(requests) => Some(requests.acquire().await),
+            None => None,
+        };
+        let res = client
+            .client
+            .execute(attempt_request)
+            .with_context(cx.clone())
+            .await;
+        drop(permit);
+        let retry: Option<(Duration, KeyValue)> = match res.as_ref() {
+            Ok(res) => {
+                let rate_limit = client.update_rate_limit(resource, res.headers());
+                if let Some(rate_limit) = rate_limit.as_ref() {
+                    span.set_attribute(Key::new("github.rate_limit.resource").string(resource));
+                    span.set_attribute(
+                        Key::new("github.rate_limit.limit").u64(rate_limit.limit.into()),
+                    );
+                    span.set_attribute(
+                        Key::new("github.rate_limit.remaining").u64(rate_limit.remaining.into()),
+                    );
+                    span.set_attribute(Key::new("github.rate_limit.reset").i64(rate_limit.reset));
+                }
+                retry_delay(res.status(), res.headers(), rate_limit.as_ref(), attempt).map(
+                    |delay| {
+                        let status_code = res.status().as_u16().into();
+                        (delay, Key::new("http.status_code").u64(status_code))
+                    },
+                )
+            }
+            Err(err) if err.is_timeout() || err.is_connect() => {
+                Some((backoff(attempt), Key::new("error").string(err.to_string())))
+            }
+            Err(_) => None,
+        };
+        match retry {
+            Some((delay, reason)) if attempt < MAX_RETRIES => {
+                attempt += 1;
+                span.add_event(
+                    "retry".into(),
+                    vec![
+                        reason,
+                        Key::new("attempt").u64(attempt.into()),
+                        Key::new("delay_ms").u64(delay.as_millis() as u64),
+                    ],
+                );
+                tokio::time::delay_for(delay).await;
+            }
+            _ => break res,
+        }
+    };
+    span.set_attribute(Key::new("github.retries").u64(attempt.into()));
 
-    let span = cx.span();
     match res.as_ref() {
         Ok(res) => {
             span.set_attribute(Key::new("http.status_code").u64(res.status().as_u16().into()));
@@ -53,18 +329,38 @@ async fn call_api<T: DeserializeOwned>(
         }
     };
 
-    let res = res?.error_for_status()?;
-    let next_page_url = match res.headers().get(LINK) {
-        Some(value) => {
-            if let Some(url) = page_links::parse(value.to_str()?).next {
-                Some(Url::parse(url)?)
-            } else {
-                None
-            }
+    let res = res?;
+    if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached)) = (res.status(), cached) {
+        span.set_attribute(Key::new("github.cache").string("hit"));
+        return Ok(Response {
+            data: serde_json::from_str(&cached.body)?,
+            next_page_url: next_page_url(cached.link.as_deref())?,
+        });
+    }
+
+    let res = res.error_for_status()?;
+    let link = header_string(res.headers(), LINK);
+    let etag = header_string(res.headers(), ETAG);
+    let last_modified = header_string(res.headers(), LAST_MODIFIED);
+    let body = res.text().await?;
+    let data: T = serde_json::from_str(&body)?;
+    let next_page_url = next_page_url(link.as_deref())?;
+
+    if let Some((cache, key)) = cache.filter(|_| etag.is_some() || last_modified.is_some()) {
+        span.set_attribute(Key::new("github.cache").string("miss"));
+        let cached = CachedResponse {
+            etag,
+            last_modified,
+            link,
+            body,
+        };
+        if let Err(err) = cache.put(&key, cached) {
+            span.add_event(
+                "cache error".into(),
+                vec![Key::new("error").string(err.to_string())],
+            );
         }
-        None => None,
-    };
-    let data: T = res.json().await?;
+    }
 
     Ok(Response {
         data,
@@ -72,13 +368,13 @@ async fn call_api<T: DeserializeOwned>(
     })
 }
 
-pub async fn get<T: DeserializeOwned>(client: &Client, url: Url) -> Result<T, BoxError> {
+pub async fn get<T: DeserializeOwned>(client: &HttpClient, url: Url) -> Result<T, BoxError> {
     let result = call_api::<T>(client, client.get(url).build()?).await?;
     Ok(result.data)
 }
 
 pub async fn post<B: Serialize + ?Sized, T: DeserializeOwned>(
-    client: &Client,
+    client: &HttpClient,
     url: Url,
     body: &B,
 ) -> Result<T, BoxError> {
@@ -87,7 +383,7 @@ pub async fn post<B: Serialize + ?Sized, T: DeserializeOwned>(
 }
 
 pub async fn post_preview<T: DeserializeOwned>(
-    client: &Client,
+    client: &HttpClient,
     url: Url,
     preview: &str,
 ) -> Result<T, BoxError> {
@@ -95,7 +391,10 @@ pub async fn post_preview<T: DeserializeOwned>(
     Ok(result.data)
 }
 
-pub async fn get_paged<T: DeserializeOwned>(client: &Client, url: Url) -> Result<Vec<T>, BoxError> {
+pub async fn get_paged<T: DeserializeOwned>(
+    client: &HttpClient,
+    url: Url,
+) -> Result<Vec<T>, BoxError> {
     let mut items = Vec::new();
 
     let mut next_page_url = Some(url);
@@ -109,7 +408,7 @@ pub async fn get_paged<T: DeserializeOwned>(client: &Client, url: Url) -> Result
 }
 
 pub async fn get_paged_preview<T: DeserializeOwned>(
-    client: &Client,
+    client: &HttpClient,
     url: Url,
     preview: &str,
 ) -> Result<Vec<T>, BoxError> {
@@ -125,3 +424,57 @@ pub async fn get_paged_preview<T: DeserializeOwned>(
 
     Ok(items)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resource_matches_rate_limit_resource_header() {
+        let resource = |url: &str| resource(&Url::parse(url).unwrap());
+        assert_eq!(resource("https://api.github.com/repos/o/r/commits"), "core");
+        assert_eq!(
+            resource("https://api.github.com/repos/o/search/commits"),
+            "core"
+        );
+        assert_eq!(
+            resource("https://api.github.com/search/commits?q=x"),
+            "search"
+        );
+        assert_eq!(
+            resource("https://ghes.example.com/api/v3/search/code"),
+            "search"
+        );
+        assert_eq!(resource("https://api.github.com/graphql"), "graphql");
+        assert_eq!(resource("https://ghes.example.com/api/graphql"), "graphql");
+    }
+
+    #[test]
+    fn tracks_rate_limits_by_resource() {
+        let client = HttpClient::new(Client::new());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit", HeaderValue::from_static("30"));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1"));
+        client.update_rate_limit("core", &headers);
+        assert_eq!(client.rate_limit("core").map(|r| r.limit), Some(30));
+
+        headers.insert("x-ratelimit-resource", HeaderValue::from_static("search"));
+        headers.insert("x-ratelimit-limit", HeaderValue::from_static("10"));
+        client.update_rate_limit("core", &headers);
+        assert_eq!(client.rate_limit("search").map(|r| r.limit), Some(10));
+        assert_eq!(client.rate_limit("core").map(|r| r.limit), Some(30));
+    }
+
+    #[test]
+    fn clamps_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("60"));
+        let delay = retry_delay(reqwest::StatusCode::FORBIDDEN, &headers, None, 0);
+        assert_eq!(delay, Some(Duration::from_secs(60)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        let delay = retry_delay(reqwest::StatusCode::TOO_MANY_REQUESTS, &headers, None, 0);
+        assert_eq!(delay, Some(MAX_RATE_LIMIT_WAIT));
+    }
+}
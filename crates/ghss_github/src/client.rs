@@ -1,14 +1,19 @@
 use super::apps;
+use super::cache::ResponseCache;
 use super::call;
 use super::models::*;
-use super::{BASE_URL, USER_AGENT};
-use chrono::{DateTime, FixedOffset};
+use super::urls::Urls;
+use super::USER_AGENT;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
 use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 pub struct Client {
-    client: reqwest::Client,
+    client: call::HttpClient,
+    urls: Urls,
 }
 
 impl Client {
@@ -33,12 +38,41 @@ impl Client {
             .default_headers(headers)
             .build()?;
 
-        Ok(Client { client })
+        Ok(Client {
+            client: call::HttpClient::new(client),
+            urls: Urls::github_com(),
+        })
+    }
+
+    /// Sends requests to the given GitHub instance instead of github.com.
+    pub fn with_base_urls(mut self, urls: Urls) -> Self {
+        self.urls = urls;
+        self
+    }
+
+    /// Uses conditional requests for GET requests and serves responses from
+    /// the cache if they were not modified. Responses are cached by URL, so
+    /// the cache must not be shared between clients of different users.
+    pub fn with_cache(mut self, cache: Arc<dyn ResponseCache>) -> Self {
+        self.client.set_cache(cache);
+        self
+    }
+
+    /// Limits the number of concurrent requests to the permits of the
+    /// semaphore. Share the semaphore to set a limit across clients.
+    pub fn with_request_limit(mut self, requests: Arc<Semaphore>) -> Self {
+        self.client.set_request_limit(requests);
+        self
+    }
+
+    /// Returns the rate limit budget of the given resource (e.g. "core" or
+    /// "graphql") as of the most recent API response.
+    pub fn rate_limit(&self, resource: &str) -> Option<RateLimit> {
+        self.client.rate_limit(resource)
     }
 
     pub async fn get_app_installations(&self) -> Result<Vec<Installation>, BoxError> {
-        let raw_url = format!("{base}/app/installations", base = BASE_URL);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&["app", "installations"])?;
         let lists: Vec<Vec<Installation>> =
             call::get_paged_preview(&self.client, url, call::MACHINE_MAN_PREVIEW).await?;
         let installations = lists.into_iter().flatten().collect();
@@ -49,19 +83,18 @@ impl Client {
         &self,
         installation_id: i32,
     ) -> Result<InstallationAccessToken, BoxError> {
-        let raw_url = format!(
-            "{base}/app/installations/{installation_id}/access_tokens",
-            base = BASE_URL,
-            installation_id = installation_id,
-        );
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&[
+            "app",
+            "installations",
+            &installation_id.to_string(),
+            "access_tokens",
+        ])?;
         let access_token = call::post_preview(&self.client, url, call::MACHINE_MAN_PREVIEW).await?;
         Ok(access_token)
     }
 
     pub async fn get_installation_repositories(&self) -> Result<Vec<Repository>, BoxError> {
-        let raw_url = format!("{base}/installation/repositories", base = BASE_URL);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&["installation", "repositories"])?;
         let lists: Vec<RepositoryList> =
             call::get_paged_preview(&self.client, url, call::MACHINE_MAN_PREVIEW).await?;
         let repositories = lists
@@ -71,16 +104,20 @@ impl Client {
         Ok(repositories)
     }
 
+    pub async fn get_rate_limits(&self) -> Result<RateLimits, BoxError> {
+        let url = self.urls.api_endpoint(&["rate_limit"])?;
+        let rate_limits = call::get(&self.client, url).await?;
+        Ok(rate_limits)
+    }
+
     pub async fn get_user(&self) -> Result<User, BoxError> {
-        let raw_url = format!("{base}/user", base = BASE_URL,);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&["user"])?;
         let user = call::get(&self.client, url).await?;
         Ok(user)
     }
 
     pub async fn get_user_installations(&self) -> Result<Vec<Installation>, BoxError> {
-        let raw_url = format!("{base}/user/installations", base = BASE_URL,);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&["user", "installations"])?;
         let lists: Vec<InstallationList> =
             call::get_paged_preview(&self.client, url, call::MACHINE_MAN_PREVIEW).await?;
         let installations = lists
@@ -94,12 +131,12 @@ impl Client {
         &self,
         installation_id: i32,
     ) -> Result<Vec<Repository>, BoxError> {
-        let raw_url = format!(
-            "{base}/user/installations/{installation_id}/repositories",
-            base = BASE_URL,
-            installation_id = installation_id
-        );
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.api_endpoint(&[
+            "user",
+            "installations",
+            &installation_id.to_string(),
+            "repositories",
+        ])?;
         let lists: Vec<RepositoryList> =
             call::get_paged_preview(&self.client, url, call::MACHINE_MAN_PREVIEW).await?;
         let repositories = lists
@@ -114,12 +151,12 @@ impl Client {
         owner: &str,
         repo: &str,
     ) -> Result<Vec<MostRecentCommit>, BoxError> {
-        let raw_url = format!("{base}/graphql", base = BASE_URL);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.graphql.clone();
         let body = GraphQLQuery {
             query: "query ($owner: String!, $name: String!) {
                 repository(owner: $owner, name: $name) {
                   defaultBranchRef {
+                    name
                     target {
                       ... on Commit {
                         history(first: 50) {
@@ -131,6 +168,20 @@ impl Client {
                       }
                     }
                   }
+                  pullRequests(states: OPEN, first: 50, orderBy: {field: UPDATED_AT, direction: DESC}) {
+                    nodes {
+                      number
+                      headRefName
+                      commits(last: 1) {
+                        nodes {
+                          commit {
+                            oid
+                            committedDate
+                          }
+                        }
+                      }
+                    }
+                  }
                 }
             }",
             variables: Some(
@@ -152,9 +203,11 @@ impl Client {
         let GraphQLResponse::<GetMostRecentCommits> { data, errors } =
             call::post(&self.client, url, &body).await?;
 
-        Ok(data
+        let repository = data
             .ok_or_else(|| format!("no data. error: {:?}", errors))?
-            .repository
+            .repository;
+        let default_branch = repository.default_branch_ref.name;
+        let default_branch_commits = repository
             .default_branch_ref
             .target
             .history
@@ -163,8 +216,154 @@ impl Client {
             .map(|node| MostRecentCommit {
                 sha: node.oid,
                 committed_date: node.committed_date,
-            })
-            .collect())
+                branch: default_branch.clone(),
+                pull_request: 0,
+            });
+        let pull_request_commits =
+            repository
+                .pull_requests
+                .nodes
+                .into_iter()
+                .flat_map(|pull_request| {
+                    let number = pull_request.number;
+                    let branch = pull_request.head_ref_name;
+                    pull_request
+                        .commits
+                        .nodes
+                        .into_iter()
+                        .map(move |node| MostRecentCommit {
+                            sha: node.commit.oid,
+                            committed_date: node.commit.committed_date,
+                            branch: branch.clone(),
+                            pull_request: number,
+                        })
+                });
+
+        Ok(default_branch_commits.chain(pull_request_commits).collect())
+    }
+
+    pub async fn get_default_branch(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<DefaultBranch, BoxError> {
+        let url = self.urls.graphql.clone();
+        let mut variables = HashMap::new();
+        variables.insert(
+            "owner".to_owned(),
+            serde_json::Value::String(owner.to_owned()),
+        );
+        variables.insert(
+            "name".to_owned(),
+            serde_json::Value::String(repo.to_owned()),
+        );
+        let body = GraphQLQuery {
+            query: "query ($owner: String!, $name: String!) {
+                repository(owner: $owner, name: $name) {
+                  defaultBranchRef {
+                    name
+                    target {
+                      oid
+                    }
+                  }
+                }
+            }",
+            variables: Some(variables),
+        };
+        let GraphQLResponse::<GetDefaultBranch> { data, errors } =
+            call::post(&self.client, url, &body).await?;
+        let branch_ref = data
+            .ok_or_else(|| format!("no data. error: {:?}", errors))?
+            .repository
+            .default_branch_ref;
+        Ok(DefaultBranch {
+            name: branch_ref.name,
+            head: branch_ref.target.oid,
+        })
+    }
+
+    /// Returns a page of the history of the given commit, starting with the
+    /// most recent commit. Commits older than `since` are not included.
+    pub async fn get_commit_history(
+        &self,
+        owner: &str,
+        repo: &str,
+        head: &str,
+        since: Option<DateTime<Utc>>,
+        after: Option<&str>,
+        first: u32,
+    ) -> Result<CommitHistoryPage, BoxError> {
+        let url = self.urls.graphql.clone();
+        let mut variables = HashMap::new();
+        variables.insert(
+            "owner".to_owned(),
+            serde_json::Value::String(owner.to_owned()),
+        );
+        variables.insert(
+            "name".to_owned(),
+            serde_json::Value::String(repo.to_owned()),
+        );
+        variables.insert(
+            "head".to_owned(),
+            serde_json::Value::String(head.to_owned()),
+        );
+        variables.insert("first".to_owned(), serde_json::Value::from(first));
+        if let Some(after) = after {
+            variables.insert(
+                "after".to_owned(),
+                serde_json::Value::String(after.to_owned()),
+            );
+        }
+        if let Some(since) = since {
+            variables.insert(
+                "since".to_owned(),
+                serde_json::Value::String(since.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            );
+        }
+        let body = GraphQLQuery {
+            query: "query ($owner: String!, $name: String!, $head: GitObjectID!, $first: Int!, $after: String, $since: GitTimestamp) {
+                repository(owner: $owner, name: $name) {
+                  object(oid: $head) {
+                    ... on Commit {
+                      history(first: $first, after: $after, since: $since) {
+                        pageInfo {
+                          hasNextPage
+                          endCursor
+                        }
+                        nodes {
+                          oid
+                          committedDate
+                        }
+                      }
+                    }
+                  }
+                }
+            }",
+            variables: Some(variables),
+        };
+        let GraphQLResponse::<GetCommitHistory> { data, errors } =
+            call::post(&self.client, url, &body).await?;
+        let history = data
+            .ok_or_else(|| format!("no data. error: {:?}", errors))?
+            .repository
+            .object
+            .ok_or_else(|| format!("commit {} not found", head))?
+            .history;
+        let page_info = history.page_info.ok_or("no page info")?;
+        Ok(CommitHistoryPage {
+            commits: history
+                .nodes
+                .into_iter()
+                .map(|node| MostRecentCommit {
+                    sha: node.oid,
+                    committed_date: node.committed_date,
+                    branch: String::new(),
+                    pull_request: 0,
+                })
+                .collect(),
+            has_next_page: page_info.has_next_page,
+            end_cursor: page_info.end_cursor,
+        })
     }
 
     pub async fn get_commit_dates(
@@ -173,8 +372,7 @@ impl Client {
         repo: &str,
         commit_shas: &[String],
     ) -> Result<Vec<DateTime<FixedOffset>>, BoxError> {
-        let raw_url = format!("{base}/graphql", base = BASE_URL);
-        let url = reqwest::Url::parse(&raw_url)?;
+        let url = self.urls.graphql.clone();
 
         let args = (0..commit_shas.len())
             .map(|i| format!(", $commit{}: GitObjectID", i))
@@ -237,10 +435,9 @@ impl Client {
         repo: &str,
         git_ref: &str,
     ) -> Result<Vec<CommitStatus>, BoxError> {
-        let mut url = reqwest::Url::parse(BASE_URL)?;
-        url.path_segments_mut()
-            .map_err(|_| "cannot be base")?
-            .extend(&["repos", owner, repo, "commits", git_ref, "statuses"]);
+        let url = self
+            .urls
+            .api_endpoint(&["repos", owner, repo, "commits", git_ref, "statuses"])?;
         let lists: Vec<Vec<CommitStatus>> = call::get_paged(&self.client, url).await?;
         let statuses = lists.into_iter().flatten().collect();
         Ok(statuses)
@@ -252,13 +449,65 @@ impl Client {
         repo: &str,
         git_ref: &str,
     ) -> Result<Vec<CheckRun>, BoxError> {
-        let mut url = reqwest::Url::parse(BASE_URL)?;
-        url.path_segments_mut()
-            .map_err(|_| "cannot be base")?
-            .extend(&["repos", owner, repo, "commits", git_ref, "check-runs"]);
+        let url =
+            self.urls
+                .api_endpoint(&["repos", owner, repo, "commits", git_ref, "check-runs"])?;
         let lists: Vec<CheckRunList> =
             call::get_paged_preview(&self.client, url, call::ANTIOPE_PREVIEW).await?;
         let check_runs = lists.into_iter().flat_map(|list| list.check_runs).collect();
         Ok(check_runs)
     }
+
+    pub async fn get_check_suites(
+        &self,
+        owner: &str,
+        repo: &str,
+        git_ref: &str,
+    ) -> Result<Vec<CheckSuite>, BoxError> {
+        let url =
+            self.urls
+                .api_endpoint(&["repos", owner, repo, "commits", git_ref, "check-suites"])?;
+        let lists: Vec<CheckSuiteList> =
+            call::get_paged_preview(&self.client, url, call::ANTIOPE_PREVIEW).await?;
+        let check_suites = lists
+            .into_iter()
+            .flat_map(|list| list.check_suites)
+            .collect();
+        Ok(check_suites)
+    }
+
+    pub async fn get_workflow_runs(
+        &self,
+        owner: &str,
+        repo: &str,
+        head_sha: &str,
+    ) -> Result<Vec<WorkflowRun>, BoxError> {
+        let mut url = self
+            .urls
+            .api_endpoint(&["repos", owner, repo, "actions", "runs"])?;
+        url.query_pairs_mut().append_pair("head_sha", head_sha);
+        let lists: Vec<WorkflowRunList> = call::get_paged(&self.client, url).await?;
+        let runs = lists
+            .into_iter()
+            .flat_map(|list| list.workflow_runs)
+            .collect();
+        Ok(runs)
+    }
+
+    /// Returns the jobs of every attempt of the workflow run.
+    pub async fn get_workflow_run_jobs(
+        &self,
+        owner: &str,
+        repo: &str,
+        run_id: i64,
+    ) -> Result<Vec<WorkflowJob>, BoxError> {
+        let run_id = run_id.to_string();
+        let mut url = self
+            .urls
+            .api_endpoint(&["repos", owner, repo, "actions", "runs", &run_id, "jobs"])?;
+        url.query_pairs_mut().append_pair("filter", "all");
+        let lists: Vec<WorkflowJobList> = call::get_paged(&self.client, url).await?;
+        let jobs = lists.into_iter().flat_map(|list| list.jobs).collect();
+        Ok(jobs)
+    }
 }
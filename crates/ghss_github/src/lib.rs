@@ -1,12 +1,15 @@
 mod apps;
+mod cache;
 mod call;
 mod client;
 mod models;
 pub mod oauth;
 mod page_links;
+mod urls;
 
+pub use cache::{CachedResponse, DiskCache, MemoryCache, ResponseCache};
 pub use client::Client;
 pub use models::*;
+pub use urls::{instance_from_env, Urls};
 
-pub const BASE_URL: &str = "https://api.github.com";
 pub const USER_AGENT: &str = concat!("github-status-stats/", env!("CARGO_PKG_VERSION"));
@@ -62,6 +62,8 @@ pub enum AppWebhookEvent {
     Team,
     TeamAdd,
     Watch,
+    WorkflowJob,
+    WorkflowRun,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -80,6 +82,8 @@ impl Default for AppPermissionLevel {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppPermissions {
+    #[serde(default)]
+    pub actions: AppPermissionLevel,
     #[serde(default)]
     pub administration: AppPermissionLevel,
     #[serde(default)]
@@ -367,6 +371,9 @@ pub enum CheckRunConclusion {
     Cancelled,
     TimedOut,
     ActionRequired,
+    Skipped,
+    Stale,
+    StartupFailure,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -384,9 +391,52 @@ pub struct CheckRun {
     pub completed_at: Option<DateTime<FixedOffset>>,
     pub output: CheckRunOutput,
     pub name: String,
-    // "check_suite": { "id": 5 },
+    pub check_suite: Option<CheckRunCheckSuite>,
+    #[serde(default)]
+    pub pull_requests: Vec<CheckRunPullRequest>,
     // "app": { ... },
-    // "pull_requests": [ { ... } ]
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckRunCheckSuite {
+    pub id: i64,
+    // Only included in webhook payloads.
+    #[serde(default)]
+    pub head_branch: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckSuite {
+    pub id: i64,
+    pub head_sha: String,
+    pub head_branch: Option<String>,
+    pub status: Option<String>,
+    pub conclusion: Option<CheckRunConclusion>,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckSuiteList {
+    pub total_count: i32,
+    pub check_suites: Vec<CheckSuite>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequestRef {
+    pub r#ref: String,
+    pub sha: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckRunPullRequest {
+    pub id: i64,
+    pub number: u32,
+    pub url: String,
+    pub head: PullRequestRef,
+    pub base: PullRequestRef,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -412,6 +462,104 @@ pub struct CheckRunEvent {
     pub sender: Account,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowStatus {
+    Requested,
+    Queued,
+    Pending,
+    Waiting,
+    InProgress,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowRun {
+    pub id: i64,
+    pub workflow_id: i64,
+    pub name: String,
+    pub head_sha: String,
+    pub head_branch: Option<String>,
+    pub run_number: i64,
+    // Missing on older GitHub Enterprise Server releases.
+    #[serde(default = "default_run_attempt")]
+    pub run_attempt: u32,
+    pub event: String,
+    pub status: WorkflowStatus,
+    pub conclusion: Option<CheckRunConclusion>,
+    pub html_url: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub updated_at: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub run_started_at: Option<DateTime<FixedOffset>>,
+    #[serde(default)]
+    pub pull_requests: Vec<CheckRunPullRequest>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowRunList {
+    pub total_count: i32,
+    pub workflow_runs: Vec<WorkflowRun>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowJob {
+    pub id: i64,
+    pub run_id: i64,
+    #[serde(default = "default_run_attempt")]
+    pub run_attempt: u32,
+    pub head_sha: String,
+    pub name: String,
+    // Only included by newer API versions.
+    #[serde(default)]
+    pub workflow_name: Option<String>,
+    #[serde(default)]
+    pub head_branch: Option<String>,
+    pub status: WorkflowStatus,
+    pub conclusion: Option<CheckRunConclusion>,
+    pub html_url: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<FixedOffset>>,
+    pub started_at: DateTime<FixedOffset>,
+    pub completed_at: Option<DateTime<FixedOffset>>,
+}
+
+fn default_run_attempt() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowJobList {
+    pub total_count: i32,
+    pub jobs: Vec<WorkflowJob>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowEventAction {
+    Requested,
+    Queued,
+    Waiting,
+    InProgress,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowRunEvent {
+    pub action: WorkflowEventAction,
+    pub workflow_run: WorkflowRun,
+    pub repository: Repository,
+    pub sender: Account,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkflowJobEvent {
+    pub action: WorkflowEventAction,
+    pub workflow_job: WorkflowJob,
+    pub repository: Repository,
+    pub sender: Account,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HookConfig {
     pub content_type: String,
@@ -499,6 +647,60 @@ pub struct StatusEvent {
     pub updated_at: DateTime<FixedOffset>,
     pub repository: Repository,
     pub sender: Account,
+    // Only included in webhooks of GitHub Apps.
+    #[serde(default)]
+    pub installation: Option<WebhookInstallation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookInstallation {
+    pub id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstallationRepository {
+    pub id: i32,
+    pub node_id: String,
+    pub name: String,
+    pub full_name: String,
+    pub private: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallationEventAction {
+    Created,
+    Deleted,
+    Suspend,
+    Unsuspend,
+    NewPermissionsAccepted,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstallationEvent {
+    pub action: InstallationEventAction,
+    pub installation: WebhookInstallation,
+    /// Only present when the installation was created or deleted.
+    #[serde(default)]
+    pub repositories: Vec<InstallationRepository>,
+    pub sender: Account,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallationRepositoriesEventAction {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InstallationRepositoriesEvent {
+    pub action: InstallationRepositoriesEventAction,
+    pub installation: WebhookInstallation,
+    pub repository_selection: String,
+    pub repositories_added: Vec<InstallationRepository>,
+    pub repositories_removed: Vec<InstallationRepository>,
+    pub sender: Account,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -507,6 +709,25 @@ pub struct GitHubAppAuthorizationEvent {
     pub sender: Account,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    /// Time the rate limit window resets in seconds since the UNIX epoch.
+    pub reset: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitResources {
+    pub core: RateLimit,
+    pub graphql: RateLimit,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimits {
+    pub resources: RateLimitResources,
+}
+
 #[derive(Debug, Serialize)]
 pub(crate) struct GraphQLQuery<'a> {
     pub query: &'a str,
@@ -549,20 +770,47 @@ pub(crate) struct GetMostRecentCommits {
 #[serde(rename_all = "camelCase")]
 pub(crate) struct GetMostRecentCommitsRepository {
     pub default_branch_ref: GetMostRecentCommitsDefaultBranchRef,
+    pub pull_requests: GetMostRecentCommitsPullRequests,
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct GetMostRecentCommitsDefaultBranchRef {
+    pub name: String,
     pub target: GetMostRecentCommitsTarget,
 }
 
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetMostRecentCommitsPullRequests {
+    pub nodes: Vec<GetMostRecentCommitsPullRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetMostRecentCommitsPullRequest {
+    pub number: u32,
+    pub head_ref_name: String,
+    pub commits: GetMostRecentCommitsPullRequestCommits,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetMostRecentCommitsPullRequestCommits {
+    pub nodes: Vec<GetMostRecentCommitsPullRequestCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetMostRecentCommitsPullRequestCommit {
+    pub commit: GetMostRecentCommitsNode,
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct GetMostRecentCommitsTarget {
     pub history: GetMostRecentCommitsHistory,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub(crate) struct GetMostRecentCommitsHistory {
+    pub page_info: Option<PageInfo>,
     pub nodes: Vec<GetMostRecentCommitsNode>,
 }
 
@@ -584,8 +832,66 @@ pub(crate) struct GetCommitDatesNode {
     pub committed_date: DateTime<FixedOffset>,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetDefaultBranch {
+    pub repository: GetDefaultBranchRepository,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetDefaultBranchRepository {
+    pub default_branch_ref: GetDefaultBranchRef,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetDefaultBranchRef {
+    pub name: String,
+    pub target: GetDefaultBranchTarget,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetDefaultBranchTarget {
+    pub oid: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetCommitHistory {
+    pub repository: GetCommitHistoryRepository,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GetCommitHistoryRepository {
+    pub object: Option<GetMostRecentCommitsTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PageInfo {
+    pub has_next_page: bool,
+    pub end_cursor: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct DefaultBranch {
+    pub name: String,
+    /// SHA of the most recent commit on the branch.
+    pub head: String,
+}
+
+#[derive(Debug)]
+pub struct CommitHistoryPage {
+    pub commits: Vec<MostRecentCommit>,
+    pub has_next_page: bool,
+    /// Cursor to pass as `after` to get the next page.
+    pub end_cursor: Option<String>,
+}
+
 #[derive(Debug)]
 pub struct MostRecentCommit {
     pub sha: String,
     pub committed_date: DateTime<FixedOffset>,
+    pub branch: String,
+    /// Pull request number, or 0 for commits on the default branch.
+    pub pull_request: u32,
 }
@@ -1,5 +1,6 @@
+use super::urls::Urls;
 use super::USER_AGENT;
-use reqwest::{Client, Url};
+use reqwest::Client;
 use serde::Deserialize;
 
 type BoxError = Box<dyn std::error::Error + Sync + Send>;
@@ -16,26 +17,30 @@ pub struct AuthToken {
     pub token_type: String,
 }
 
-pub fn login_url(client_id: &str, redirect_uri: &str, state: Option<String>) -> String {
-    Url::parse_with_params(
-        "https://github.com/login/oauth/authorize",
-        &[
-            ("client_id", client_id),
-            ("redirect_uri", redirect_uri),
-            ("state", state.as_ref().unwrap_or(&"".to_owned())),
-        ],
-    )
-    .expect("cannot parse GitHub base url")
-    .into_string()
+pub fn login_url(
+    urls: &Urls,
+    client_id: &str,
+    redirect_uri: &str,
+    state: Option<String>,
+) -> String {
+    let mut url = urls
+        .web_endpoint(&["login", "oauth", "authorize"])
+        .expect("cannot parse GitHub base url");
+    url.query_pairs_mut()
+        .append_pair("client_id", client_id)
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("state", state.as_ref().unwrap_or(&"".to_owned()));
+    url.into_string()
 }
 
 pub async fn exchange_code(
+    urls: &Urls,
     client_id: &str,
     client_secret: &str,
     redirect_uri: &str,
     code: &AuthCodeQuery,
 ) -> Result<AuthToken, BoxError> {
-    let url = Url::parse("https://github.com/login/oauth/access_token")?;
+    let url = urls.web_endpoint(&["login", "oauth", "access_token"])?;
     let res = Client::new()
         .post(url)
         .header(reqwest::header::USER_AGENT, USER_AGENT)
@@ -0,0 +1,102 @@
+use reqwest::Url;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Base URLs of a GitHub instance.
+#[derive(Debug, Clone)]
+pub struct Urls {
+    /// REST API, e.g. `https://api.github.com`.
+    pub api: Url,
+    /// GraphQL endpoint, e.g. `https://api.github.com/graphql`.
+    pub graphql: Url,
+    /// Web interface used for OAuth, e.g. `https://github.com`.
+    pub web: Url,
+}
+
+impl Urls {
+    pub fn github_com() -> Self {
+        Self {
+            api: Url::parse("https://api.github.com").expect("valid api url"),
+            graphql: Url::parse("https://api.github.com/graphql").expect("valid graphql url"),
+            web: Url::parse("https://github.com").expect("valid web url"),
+        }
+    }
+
+    /// URLs of a GitHub Enterprise Server instance, e.g. `github.example.com`.
+    pub fn enterprise(host: &str) -> Result<Self, BoxError> {
+        Ok(Self {
+            api: Url::parse(&format!("https://{}/api/v3", host))?,
+            graphql: Url::parse(&format!("https://{}/api/graphql", host))?,
+            web: Url::parse(&format!("https://{}", host))?,
+        })
+    }
+
+    /// URLs configured by the environment: those of the GitHub Enterprise
+    /// Server instance in `GH_ENTERPRISE_HOST` or of github.com, with single
+    /// URLs overridden by `GH_API_URL`, `GH_GRAPHQL_URL` and `GH_WEB_URL`.
+    /// Panics if a variable is invalid.
+    pub fn from_env() -> Self {
+        let mut urls = match std::env::var("GH_ENTERPRISE_HOST") {
+            Ok(host) => {
+                Urls::enterprise(&host).unwrap_or_else(|_| panic!("env GH_ENTERPRISE_HOST invalid"))
+            }
+            Err(_) => Urls::github_com(),
+        };
+        if let Some(api) = url_env("GH_API_URL") {
+            urls.api = api;
+        }
+        if let Some(graphql) = url_env("GH_GRAPHQL_URL") {
+            urls.graphql = graphql;
+        }
+        if let Some(web) = url_env("GH_WEB_URL") {
+            urls.web = web;
+        }
+        urls
+    }
+
+    pub(crate) fn api_endpoint(&self, segments: &[&str]) -> Result<Url, BoxError> {
+        append_segments(&self.api, segments)
+    }
+
+    pub fn web_endpoint(&self, segments: &[&str]) -> Result<Url, BoxError> {
+        append_segments(&self.web, segments)
+    }
+}
+
+impl Default for Urls {
+    fn default() -> Self {
+        Self::github_com()
+    }
+}
+
+fn url_env(name: &str) -> Option<Url> {
+    std::env::var(name)
+        .ok()
+        .map(|url| Url::parse(&url).unwrap_or_else(|_| panic!("env {} invalid", name)))
+}
+
+/// Name of the GitHub instance in `GH_INSTANCE`, which namespaces its
+/// repositories in a store shared with other instances. Not set for
+/// github.com. Panics if the name is invalid.
+pub fn instance_from_env() -> Option<String> {
+    let instance = std::env::var("GH_INSTANCE").ok();
+    if let Some(instance) = instance.as_ref() {
+        let valid = !instance.is_empty()
+            && instance
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if !valid {
+            panic!("env GH_INSTANCE invalid");
+        }
+    }
+    instance
+}
+
+fn append_segments(base: &Url, segments: &[&str]) -> Result<Url, BoxError> {
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .map_err(|_| "cannot be base")?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
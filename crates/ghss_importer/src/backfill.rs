@@ -0,0 +1,93 @@
+use crate::config::BackfillConfig;
+use crate::store::RepositoryImporter;
+use ghss_builds::stream_builds;
+use ghss_github::{Client, Repository};
+use ghss_store_client::BackfillState;
+use ghss_tracing::log_event;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+async fn has_rate_limit_left(client: &Client, config: &BackfillConfig) -> Result<bool, BoxError> {
+    let rate_limits = client.get_rate_limits().await?;
+    Ok(
+        rate_limits.resources.core.remaining >= config.min_rate_limit
+            && rate_limits.resources.graphql.remaining >= config.min_rate_limit,
+    )
+}
+
+/// Imports builds of older default branch commits, page by page. The progress
+/// is stored together with every page, so an interrupted backfill resumes
+/// where it stopped in the next run.
+pub async fn backfill(
+    client: &Client,
+    importer: &mut RepositoryImporter<'_>,
+    repository: &Repository,
+    config: &BackfillConfig,
+    max_concurrent_commits: usize,
+) -> Result<(), BoxError> {
+    let mut state = match importer.get_backfill_state().await?.into_inner().state {
+        Some(state) if state.completed => return Ok(()),
+        Some(state) => state,
+        None => {
+            let default_branch = client
+                .get_default_branch(&repository.owner.login, &repository.name)
+                .await?;
+            BackfillState {
+                head: default_branch.head,
+                branch: default_branch.name,
+                ..Default::default()
+            }
+        }
+    };
+
+    while state.commits < config.max_commits {
+        if !has_rate_limit_left(client, config).await? {
+            log_event("rate limit low; pausing backfill until next run".into());
+            return Ok(());
+        }
+
+        let page = client
+            .get_commit_history(
+                &repository.owner.login,
+                &repository.name,
+                &state.head,
+                config.since,
+                Some(state.cursor.as_str()).filter(|cursor| !cursor.is_empty()),
+                config.page_size.min(config.max_commits - state.commits),
+            )
+            .await?;
+        let commits_len = page.commits.len() as u32;
+        let commits = page
+            .commits
+            .into_iter()
+            .map(|mut commit| {
+                commit.branch = state.branch.clone();
+                commit
+            })
+            .collect();
+
+        state.commits += commits_len;
+        state.cursor = page.end_cursor.unwrap_or_default();
+        // An empty page would be requested again forever.
+        state.completed = !page.has_next_page || commits_len == 0;
+        log_event(format!("backfilled {} commits", state.commits));
+        importer
+            .import_with_backfill(
+                stream_builds(
+                    client,
+                    &repository.owner.login,
+                    &repository.name,
+                    commits,
+                    max_concurrent_commits,
+                ),
+                Some(state.clone()),
+            )
+            .await?;
+        if state.completed {
+            return Ok(());
+        }
+    }
+
+    // The limit isn't stored as completed, so raising it resumes the backfill.
+    Ok(())
+}
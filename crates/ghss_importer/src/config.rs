@@ -1,10 +1,55 @@
+use chrono::{DateTime, Utc};
+use ghss_github::{instance_from_env, Urls};
+use ghss_store_client::TlsConfig;
 use secstr::SecUtf8;
 
+pub struct BackfillConfig {
+    /// Don't backfill commits older than this.
+    pub since: Option<DateTime<Utc>>,
+    /// Maximum number of default branch commits to backfill per repository.
+    pub max_commits: u32,
+    /// Number of commits imported per batch. Progress is checkpointed after
+    /// every batch.
+    pub page_size: u32,
+    /// Pause the backfill until the next run once fewer GitHub API requests
+    /// than this are remaining.
+    pub min_rate_limit: u32,
+}
+
+pub struct ConcurrencyConfig {
+    /// Installations imported at the same time.
+    pub installations: usize,
+    /// Repositories imported at the same time per installation.
+    pub repositories: usize,
+    /// Commits fetched at the same time per repository.
+    pub commits: usize,
+    /// GitHub API requests in flight at the same time across all
+    /// installations.
+    pub requests: usize,
+}
+
+pub struct CacheConfig {
+    /// Responses are stored in a subdirectory per installation.
+    pub directory: String,
+    /// Maximum number of responses stored per installation.
+    pub max_entries: usize,
+}
+
 pub struct Config {
+    pub gh_urls: Urls,
+    /// Name of the GitHub instance used to namespace repositories in the
+    /// store. Not set for github.com.
+    pub gh_instance: Option<String>,
     pub gh_app_id: String,
     pub gh_private_key: SecUtf8,
     pub store_url: String,
+    /// Secret shared with the store to sign bearer tokens.
+    pub store_token_secret: Option<SecUtf8>,
+    pub store_tls: Option<TlsConfig>,
     pub otel_agent_endpoint: Option<String>,
+    pub gh_cache: Option<CacheConfig>,
+    pub backfill: BackfillConfig,
+    pub concurrency: ConcurrencyConfig,
 }
 
 fn env(name: &str) -> String {
@@ -15,11 +60,62 @@ fn option_env(name: &str) -> Option<String> {
     std::env::var(name).ok()
 }
 
+fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    option_env(name)
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("env {} invalid", name))
+        })
+        .unwrap_or(default)
+}
+
+// Concurrency limits and page sizes of 0 would never make progress.
+fn parse_nonzero_env<T: std::str::FromStr + PartialEq + From<u8>>(name: &str, default: T) -> T {
+    let value = parse_env(name, default);
+    if value == T::from(0) {
+        panic!("env {} invalid", name);
+    }
+    value
+}
+
+fn load_store_tls() -> Option<TlsConfig> {
+    option_env("STORE_TLS_CA_PATH").map(|ca_certificate_path| TlsConfig {
+        ca_certificate_path,
+        identity: option_env("STORE_TLS_CERTIFICATE_PATH")
+            .map(|certificate_path| (certificate_path, env("STORE_TLS_KEY_PATH"))),
+    })
+}
+
 pub fn load() -> Config {
     Config {
+        gh_urls: Urls::from_env(),
+        gh_instance: instance_from_env(),
         gh_app_id: env("GH_APP_ID"),
         gh_private_key: SecUtf8::from(env("GH_PRIVATE_KEY")),
         store_url: env("STORE_URL"),
+        store_token_secret: option_env("STORE_TOKEN_SECRET").map(SecUtf8::from),
+        store_tls: load_store_tls(),
         otel_agent_endpoint: option_env("OTEL_AGENT_ENDPOINT"),
+        gh_cache: option_env("GH_CACHE_DIRECTORY").map(|directory| CacheConfig {
+            directory,
+            max_entries: parse_env("GH_CACHE_MAX_ENTRIES", 10_000),
+        }),
+        backfill: BackfillConfig {
+            since: option_env("BACKFILL_SINCE").map(|since| {
+                DateTime::parse_from_rfc3339(&since)
+                    .unwrap_or_else(|_| panic!("env BACKFILL_SINCE invalid"))
+                    .with_timezone(&Utc)
+            }),
+            max_commits: parse_env("BACKFILL_MAX_COMMITS", 500),
+            page_size: parse_nonzero_env("BACKFILL_PAGE_SIZE", 50),
+            min_rate_limit: parse_env("BACKFILL_MIN_RATE_LIMIT", 500),
+        },
+        concurrency: ConcurrencyConfig {
+            installations: parse_nonzero_env("MAX_CONCURRENT_INSTALLATIONS", 2),
+            repositories: parse_nonzero_env("MAX_CONCURRENT_REPOSITORIES", 4),
+            commits: parse_nonzero_env("MAX_CONCURRENT_COMMITS", 8),
+            requests: parse_nonzero_env("MAX_CONCURRENT_REQUESTS", 16),
+        },
     }
 }
@@ -1,106 +1,270 @@
-mod build;
+mod backfill;
 mod config;
 mod store;
 
-use build::{get_builds_from_commit_shas, get_most_recent_builds};
-use config::Config;
-use ghss_github::{Client, Repository};
-use ghss_store_client::StoreClient;
-use ghss_store_client::Code;
+use chrono::Utc;
+use config::{BackfillConfig, CacheConfig, ConcurrencyConfig, Config};
+use futures::stream::{self, StreamExt};
+use ghss_builds::{get_hooked_commits, stream_builds};
+use ghss_github::{Client, DiskCache, Repository, Urls};
+use ghss_store_client::{
+    connect_channel, store_repository_id, Code, Credentials, RegisteredRepository,
+    RepositoriesRequest, RepositoryStatus, Scope, StoreClient, UpdateRepositoriesRequest,
+};
 use ghss_tracing::{init_tracer, log_event};
 use opentelemetry::api::{Context, FutureExt, Key, StatusCode, TraceContextExt, Tracer};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
 use store::RepositoryImporter;
+use tokio::sync::Semaphore;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+struct ImportOptions<'a> {
+    gh_urls: &'a Urls,
+    gh_instance: Option<&'a str>,
+    backfill: &'a BackfillConfig,
+    concurrency: &'a ConcurrencyConfig,
+    gh_cache: Option<&'a CacheConfig>,
+    // Shared by all GitHub clients to limit the concurrent requests globally.
+    gh_requests: Arc<Semaphore>,
+    // Lifecycle status of registered repositories by store repository ID.
+    repository_statuses: HashMap<String, RepositoryStatus>,
+}
+
+fn set_span_error(cx: &Context, err: &BoxError) {
+    let span = cx.span();
+    span.set_status(StatusCode::Internal, err.to_string());
+    span.set_attribute(Key::new("error").string(err.to_string()));
+}
+
 async fn import_repository(
     gh_inst_client: &Client,
     store_client: &mut StoreClient,
     repository: &Repository,
+    options: &ImportOptions<'_>,
 ) -> Result<(), BoxError> {
-    let mut importer = RepositoryImporter::new(store_client, repository.id.to_string());
+    let mut importer = RepositoryImporter::new(
+        store_client,
+        store_repository_id(options.gh_instance, repository.id),
+    );
+    let commit_concurrency = options.concurrency.commits;
 
     let commits_since = importer.get_hooked_commits_since_last_import().await;
     match commits_since {
         Ok(commits_since) => {
             log_event("found last import; importing since then".into());
-            let commit_shas: Vec<String> = commits_since
-                .into_inner()
-                .commits
-                .into_iter()
-                .map(|commit| commit.commit)
-                .collect();
-            if !commit_shas.is_empty() {
-                let (builds, commits) =
-                    get_builds_from_commit_shas(gh_inst_client, repository, commit_shas).await?;
-                importer.import(builds, commits).await?;
+            let hooked_commits = commits_since.into_inner().commits;
+            if !hooked_commits.is_empty() {
+                let commits = get_hooked_commits(
+                    gh_inst_client,
+                    &repository.owner.login,
+                    &repository.name,
+                    hooked_commits,
+                )
+                .await?;
+                importer
+                    .import(stream_builds(
+                        gh_inst_client,
+                        &repository.owner.login,
+                        &repository.name,
+                        commits,
+                        commit_concurrency,
+                    ))
+                    .await?;
             }
         }
         Err(status) if status.code() == Code::FailedPrecondition => {
             log_event("first import; setup db and perform initial import".into());
-            let (builds, commits) = get_most_recent_builds(&gh_inst_client, &repository).await?;
-            importer.import(builds, commits).await?;
+            let commits = gh_inst_client
+                .get_most_recent_commits(&repository.owner.login, &repository.name)
+                .await?;
+            importer
+                .import(stream_builds(
+                    gh_inst_client,
+                    &repository.owner.login,
+                    &repository.name,
+                    commits,
+                    commit_concurrency,
+                ))
+                .await?;
         }
         Err(status) => {
             return Err(status.into());
         }
     }
 
+    backfill::backfill(
+        gh_inst_client,
+        &mut importer,
+        repository,
+        options.backfill,
+        commit_concurrency,
+    )
+    .await?;
+
     Ok(())
 }
 
+/// Skips repositories that aren't active and registers the ones the store
+/// doesn't know yet, e.g. because they were added while the website was down.
+async fn register_repositories(
+    store_client: &mut StoreClient,
+    installation_id: i32,
+    repositories: Vec<Repository>,
+    options: &ImportOptions<'_>,
+) -> Result<Vec<Repository>, BoxError> {
+    let mut active = Vec::new();
+    let mut unknown = Vec::new();
+    for repository in repositories {
+        let repository_id = store_repository_id(options.gh_instance, repository.id);
+        match options.repository_statuses.get(&repository_id) {
+            Some(RepositoryStatus::Active) => active.push(repository),
+            Some(status) => log_event(format!(
+                "skipping repository {} with status {:?}",
+                repository.full_name, status
+            )),
+            None => {
+                unknown.push(RegisteredRepository {
+                    repository_id,
+                    installation_id: installation_id.into(),
+                    full_name: repository.full_name.clone(),
+                    status: RepositoryStatus::Active as i32,
+                    updated_at: Utc::now().timestamp_millis(),
+                });
+                active.push(repository);
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        store_client
+            .update_repositories(UpdateRepositoriesRequest {
+                repositories: unknown,
+            })
+            .await?;
+    }
+    Ok(active)
+}
+
 async fn import_installation(
     gh_app_client: &Client,
-    store_client: &mut StoreClient,
+    store_client: &StoreClient,
     installation_id: i32,
+    options: &ImportOptions<'_>,
 ) -> Result<(), BoxError> {
     let tracer = opentelemetry::global::tracer("importer");
     let token = gh_app_client
         .create_app_installation_access_token(installation_id)
         .await?;
-    let gh_inst_client = Client::new(&token.token)?;
-    let repositories = gh_inst_client.get_installation_repositories().await?;
-    for repository in repositories {
-        let span = tracer
-            .span_builder("repository")
-            .with_attributes(vec![
-                Key::new("repository.id").i64(repository.id.into()),
-                Key::new("repository.full_name").string(repository.full_name.clone()),
-            ])
-            .start(&tracer);
-        let cx = Context::current_with_span(span);
-        let res = import_repository(&gh_inst_client, store_client, &repository)
-            .with_context(cx.clone())
-            .await;
-        if let Err(err) = res {
-            let span = cx.span();
-            span.set_status(StatusCode::Internal, err.to_string());
-            span.set_attribute(Key::new("error").string(err.to_string()));
-        }
+    let mut gh_inst_client = Client::new(&token.token)?
+        .with_base_urls(options.gh_urls.clone())
+        .with_request_limit(options.gh_requests.clone());
+    // Responses depend on the installation the token belongs to, so each
+    // installation has its own cache.
+    if let Some(gh_cache) = options.gh_cache {
+        let directory = Path::new(&gh_cache.directory).join(installation_id.to_string());
+        let cache = DiskCache::new(directory, gh_cache.max_entries)?;
+        gh_inst_client = gh_inst_client.with_cache(Arc::new(cache));
     }
+    let repositories = gh_inst_client.get_installation_repositories().await?;
+    let repositories = register_repositories(
+        &mut store_client.clone(),
+        installation_id,
+        repositories,
+        options,
+    )
+    .await?;
+    // Repositories are imported concurrently within this task, so the parent
+    // has to be set explicitly instead of relying on the current context.
+    let parent_cx = Context::current();
+    stream::iter(repositories)
+        .map(|repository| {
+            let span = tracer
+                .span_builder("repository")
+                .with_parent(parent_cx.span().span_context())
+                .with_attributes(vec![
+                    Key::new("repository.id").i64(repository.id.into()),
+                    Key::new("repository.full_name").string(repository.full_name.clone()),
+                ])
+                .start(&tracer);
+            let cx = parent_cx.with_span(span);
+            let mut store_client = store_client.clone();
+            let gh_inst_client = &gh_inst_client;
+            async move {
+                let res =
+                    import_repository(gh_inst_client, &mut store_client, &repository, options)
+                        .with_context(cx.clone())
+                        .await;
+                if let Err(err) = res {
+                    set_span_error(&cx, &err);
+                }
+            }
+        })
+        .buffer_unordered(options.concurrency.repositories)
+        .collect::<()>()
+        .await;
     Ok(())
 }
 
 async fn import(config: Config) -> Result<(), BoxError> {
     let tracer = opentelemetry::global::tracer("importer");
-    let mut store_client = StoreClient::connect(config.store_url).await?;
-    let gh_app_client = Client::new_app_auth(&config.gh_app_id, &config.gh_private_key.unsecure())?;
-    let installations = gh_app_client.get_app_installations().await?;
-    for installation in installations {
-        let span = tracer
-            .span_builder("installation")
-            .with_attributes(vec![Key::new("installation.id").i64(installation.id.into())])
-            .start(&tracer);
-        let cx = Context::current_with_span(span);
-        let res = import_installation(&gh_app_client, &mut store_client, installation.id)
-            .with_context(cx.clone())
-            .await;
-        if let Err(err) = res {
-            let span = cx.span();
-            span.set_status(StatusCode::Internal, err.to_string());
-            span.set_attribute(Key::new("error").string(err.to_string()));
-        }
+    let channel = connect_channel(config.store_url, config.store_tls.as_ref()).await?;
+    let mut store_client = StoreClient::new(channel);
+    if let Some(secret) = &config.store_token_secret {
+        store_client = store_client.with_credentials(Credentials::new(
+            secret.unsecure().as_bytes(),
+            "importer",
+            &[Scope::Store],
+        ));
     }
+    let repository_statuses = store_client
+        .get_repositories(RepositoriesRequest {})
+        .await?
+        .into_inner()
+        .repositories
+        .into_iter()
+        .map(|repository| (repository.repository_id.clone(), repository.status()))
+        .collect();
+    let gh_requests = Arc::new(Semaphore::new(config.concurrency.requests));
+    let gh_app_client = Client::new_app_auth(&config.gh_app_id, config.gh_private_key.unsecure())?
+        .with_base_urls(config.gh_urls.clone())
+        .with_request_limit(gh_requests.clone());
+    let options = ImportOptions {
+        gh_urls: &config.gh_urls,
+        gh_instance: config.gh_instance.as_deref(),
+        backfill: &config.backfill,
+        concurrency: &config.concurrency,
+        gh_cache: config.gh_cache.as_ref(),
+        gh_requests,
+        repository_statuses,
+    };
+    let installations = gh_app_client.get_app_installations().await?;
+    let parent_cx = Context::current();
+    stream::iter(installations)
+        .map(|installation| {
+            let span = tracer
+                .span_builder("installation")
+                .with_parent(parent_cx.span().span_context())
+                .with_attributes(vec![Key::new("installation.id").i64(installation.id.into())])
+                .start(&tracer);
+            let cx = parent_cx.with_span(span);
+            let gh_app_client = &gh_app_client;
+            let store_client = &store_client;
+            let options = &options;
+            async move {
+                let res =
+                    import_installation(gh_app_client, store_client, installation.id, options)
+                        .with_context(cx.clone())
+                        .await;
+                if let Err(err) = res {
+                    set_span_error(&cx, &err);
+                }
+            }
+        })
+        .buffer_unordered(config.concurrency.installations)
+        .collect::<()>()
+        .await;
 
     Ok(())
 }
@@ -1,7 +1,9 @@
 use chrono::{DateTime, Utc};
+use futures::Stream;
 use ghss_store_client::StoreClient;
 use ghss_store_client::{
-    Build, Commit, HookedCommitsReply, HookedCommitsRequest, ImportRequest, Response, Status,
+    BackfillState, BackfillStateReply, BackfillStateRequest, Build, Commit, HookedCommitsReply,
+    HookedCommitsRequest, Response, Status,
 };
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
@@ -21,21 +23,38 @@ impl<'client> RepositoryImporter<'client> {
         }
     }
 
-    pub async fn import(
+    /// Imports the batches of builds and commits as they are fetched.
+    pub async fn import<S>(&mut self, batches: S) -> Result<(), BoxError>
+    where
+        S: Stream<Item = Result<(Vec<Build>, Vec<Commit>), BoxError>>,
+    {
+        self.import_with_backfill(batches, None).await
+    }
+
+    pub async fn import_with_backfill<S>(
         &mut self,
-        builds: Vec<Build>,
-        commits: Vec<Commit>,
-    ) -> Result<(), BoxError> {
-        let _response = self
-            .client
-            .import(ImportRequest {
+        batches: S,
+        backfill: Option<BackfillState>,
+    ) -> Result<(), BoxError>
+    where
+        S: Stream<Item = Result<(Vec<Build>, Vec<Commit>), BoxError>>,
+    {
+        self.client
+            .import_batches(
+                self.repository_id.clone(),
+                self.timestamp.timestamp_millis(),
+                backfill,
+                batches,
+            )
+            .await
+    }
+
+    pub async fn get_backfill_state(&mut self) -> Result<Response<BackfillStateReply>, Status> {
+        self.client
+            .get_backfill_state(BackfillStateRequest {
                 repository_id: self.repository_id.clone(),
-                builds,
-                commits,
-                timestamp: self.timestamp.timestamp_millis(),
             })
-            .await?;
-        Ok(())
+            .await
     }
 
     pub async fn get_hooked_commits_since_last_import(
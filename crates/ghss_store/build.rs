@@ -1,11 +1,11 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure().build_client(false).compile(
-        &[
-            "proto/store.proto",
-            "proto/query.proto",
-            "proto/health.proto",
-        ],
-        &["proto"],
-    )?;
-    Ok(())
-}
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure().build_client(false).compile(
+        &[
+            "proto/store.proto",
+            "proto/query.proto",
+            "proto/health.proto",
+        ],
+        &["proto"],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::sync::Arc;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::{Code, Request, Status};
+
+pub const SCOPE_STORE: &str = "store";
+pub const SCOPE_QUERY: &str = "query";
+/// Grants access to all services, including deleting data.
+pub const SCOPE_ADMIN: &str = "admin";
+
+// Set by the interceptor after validating the token. Values sent by clients
+// are removed, so handlers can trust them.
+const AUTHORIZED_SCOPES: &str = "x-ghss-authorized-scopes";
+const AUTHORIZED_REPOSITORY: &str = "x-ghss-authorized-repository";
+
+#[derive(Debug)]
+pub enum Error {
+    Unauthenticated(String),
+    PermissionDenied(String),
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Unauthenticated(message) => Status::new(Code::Unauthenticated, message),
+            Error::PermissionDenied(message) => Status::new(Code::PermissionDenied, message),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    #[allow(dead_code)]
+    exp: u64,
+    sub: String,
+    scopes: Vec<String>,
+    /// Restricts the token to a single repository.
+    repository_id: Option<String>,
+}
+
+/// Validates bearer tokens signed with a shared secret (HS256).
+pub struct Authenticator {
+    key: DecodingKey<'static>,
+}
+
+impl Authenticator {
+    pub fn new(secret: &[u8]) -> Authenticator {
+        Authenticator {
+            key: DecodingKey::from_secret(secret).into_static(),
+        }
+    }
+
+    fn validate(&self, token: &str) -> Result<Claims, Error> {
+        decode::<Claims>(token, &self.key, &Validation::new(Algorithm::HS256))
+            .map(|token| token.claims)
+            .map_err(|err| Error::Unauthenticated(format!("Invalid token: {}", err)))
+    }
+}
+
+fn bearer_token<T>(request: &Request<T>) -> Result<&str, Error> {
+    request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| Error::Unauthenticated("Bearer token missing".to_owned()))
+}
+
+fn metadata_value(value: &str) -> Result<MetadataValue<Ascii>, Error> {
+    MetadataValue::from_str(value)
+        .map_err(|_| Error::Unauthenticated("Invalid token claims".to_owned()))
+}
+
+fn intercept(
+    authenticator: Option<&Authenticator>,
+    scope: &str,
+    mut request: Request<()>,
+) -> Result<Request<()>, Error> {
+    request.metadata_mut().remove(AUTHORIZED_SCOPES);
+    request.metadata_mut().remove(AUTHORIZED_REPOSITORY);
+    let authenticator = match authenticator {
+        Some(authenticator) => authenticator,
+        None => {
+            request
+                .metadata_mut()
+                .insert(AUTHORIZED_SCOPES, metadata_value(scope)?);
+            return Ok(request);
+        }
+    };
+
+    let claims = authenticator.validate(bearer_token(&request)?)?;
+    if !claims
+        .scopes
+        .iter()
+        .any(|claim| claim == scope || claim == SCOPE_ADMIN)
+    {
+        return Err(Error::PermissionDenied(format!(
+            "Token of {} lacks scope {}",
+            claims.sub, scope
+        )));
+    }
+    request
+        .metadata_mut()
+        .insert(AUTHORIZED_SCOPES, metadata_value(&claims.scopes.join(","))?);
+    if let Some(repository_id) = &claims.repository_id {
+        request
+            .metadata_mut()
+            .insert(AUTHORIZED_REPOSITORY, metadata_value(repository_id)?);
+    }
+    Ok(request)
+}
+
+/// Returns an interceptor that only lets requests with a token for the given
+/// scope through. Without an authenticator all requests get the scope, but
+/// never the admin scope.
+// The signature of interceptors is given by tonic.
+#[allow(clippy::result_large_err)]
+pub fn interceptor(
+    authenticator: Option<Arc<Authenticator>>,
+    scope: &'static str,
+) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+    move |request| Ok(intercept(authenticator.as_deref(), scope, request)?)
+}
+
+/// Checks that the token of an intercepted request grants access to the
+/// repository.
+pub fn authorize_repository<T>(request: &Request<T>, repository_id: &str) -> Result<(), Error> {
+    match request.metadata().get(AUTHORIZED_REPOSITORY) {
+        Some(authorized) if authorized != repository_id => Err(Error::PermissionDenied(format!(
+            "Token doesn't grant access to repository {}",
+            repository_id
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the token of an intercepted request isn't restricted to a
+/// single repository.
+pub fn authorize_all_repositories<T>(request: &Request<T>) -> Result<(), Error> {
+    if request.metadata().get(AUTHORIZED_REPOSITORY).is_some() {
+        return Err(Error::PermissionDenied(
+            "Token is restricted to a single repository".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the token of an intercepted request has the admin scope.
+pub fn authorize_admin<T>(request: &Request<T>) -> Result<(), Error> {
+    let is_admin = request
+        .metadata()
+        .get(AUTHORIZED_SCOPES)
+        .and_then(|scopes| scopes.to_str().ok())
+        .map(|scopes| scopes.split(',').any(|scope| scope == SCOPE_ADMIN))
+        .unwrap_or(false);
+    if is_admin {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied("Admin scope required".to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unauthenticated_requests_arent_admin() {
+        let request = intercept(None, SCOPE_STORE, Request::new(())).unwrap();
+        assert!(authorize_repository(&request, "1").is_ok());
+        assert!(authorize_admin(&request).is_err());
+    }
+
+    #[test]
+    fn ignores_scopes_sent_by_clients() {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(AUTHORIZED_SCOPES, metadata_value(SCOPE_ADMIN).unwrap());
+        let request = intercept(None, SCOPE_QUERY, request).unwrap();
+        assert!(authorize_admin(&request).is_err());
+    }
+}
@@ -1,19 +1,74 @@
-pub struct Config {
-    pub database_directory: String,
-    pub otel_agent_endpoint: Option<String>,
-}
-
-fn env(name: &str) -> String {
-    std::env::var(name).unwrap_or_else(|_| panic!("env {}", name))
-}
-
-fn option_env(name: &str) -> Option<String> {
-    std::env::var(name).ok()
-}
-
-pub fn load() -> Config {
-    Config {
-        database_directory: env("DATABASE_DIRECTORY"),
-        otel_agent_endpoint: option_env("OTEL_AGENT_ENDPOINT"),
-    }
-}
+pub struct TlsConfig {
+    pub certificate_path: String,
+    pub key_path: String,
+    /// Requires clients to present a certificate signed by this CA.
+    pub client_ca_path: Option<String>,
+}
+
+pub struct Config {
+    pub database_directory: String,
+    pub otel_agent_endpoint: Option<String>,
+    pub repository_retention_days: Option<i64>,
+    /// Secret used to verify bearer tokens. Only unset if authentication is
+    /// explicitly disabled, in which case requests get the scope of the
+    /// service they call but never the admin scope.
+    pub auth_token_secret: Option<String>,
+    pub tls: Option<TlsConfig>,
+    /// Maximum number of databases kept open, separately for readers and
+    /// writers. Databases in use count against it.
+    pub database_pool_size: usize,
+    pub database_max_idle: std::time::Duration,
+    /// Number of threads running database work.
+    pub database_threads: usize,
+    /// Database work of a call is interrupted after this time.
+    pub database_call_timeout: std::time::Duration,
+    /// Streamed imports are held in memory until they finish, so their size
+    /// is limited.
+    pub import_max_rows: usize,
+    pub import_max_bytes: usize,
+}
+
+fn env(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| panic!("env {}", name))
+}
+
+fn option_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn option_parse_env<T: std::str::FromStr>(name: &str) -> Option<T> {
+    option_env(name).map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("env {} invalid", name))
+    })
+}
+
+pub fn load() -> Config {
+    let auth_disabled = option_parse_env("AUTH_DISABLED").unwrap_or(false);
+    Config {
+        database_directory: env("DATABASE_DIRECTORY"),
+        otel_agent_endpoint: option_env("OTEL_AGENT_ENDPOINT"),
+        repository_retention_days: option_parse_env("REPOSITORY_RETENTION_DAYS"),
+        auth_token_secret: if auth_disabled {
+            None
+        } else {
+            Some(env("AUTH_TOKEN_SECRET"))
+        },
+        tls: option_env("TLS_CERTIFICATE_PATH").map(|certificate_path| TlsConfig {
+            certificate_path,
+            key_path: env("TLS_KEY_PATH"),
+            client_ca_path: option_env("TLS_CLIENT_CA_PATH"),
+        }),
+        database_pool_size: option_parse_env("DATABASE_POOL_SIZE").unwrap_or(64),
+        database_max_idle: std::time::Duration::from_secs(
+            option_parse_env("DATABASE_MAX_IDLE_SECONDS").unwrap_or(300),
+        ),
+        database_threads: option_parse_env("DATABASE_THREADS").unwrap_or(8),
+        database_call_timeout: std::time::Duration::from_secs(
+            option_parse_env("DATABASE_CALL_TIMEOUT_SECONDS").unwrap_or(30),
+        ),
+        import_max_rows: option_parse_env("IMPORT_MAX_ROWS").unwrap_or(1_000_000),
+        import_max_bytes: option_parse_env("IMPORT_MAX_BYTES").unwrap_or(256 * 1024 * 1024),
+    }
+}
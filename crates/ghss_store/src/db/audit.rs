@@ -0,0 +1,83 @@
+use super::{system_path, Result};
+use crate::proto::AuditLogEntry;
+use rusqlite::{params, Connection};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id            INTEGER PRIMARY KEY,
+        timestamp     INTEGER NOT NULL,
+        action        TEXT NOT NULL,
+        repository_id TEXT NOT NULL,
+        since         INTEGER NOT NULL,
+        until         INTEGER NOT NULL,
+        reason        TEXT NOT NULL,
+        requested_by  TEXT NOT NULL,
+        result        TEXT NOT NULL
+    );";
+
+/// Log of destructive actions on repository data. Entries are written before
+/// the action starts and updated with the result afterwards, so an action
+/// interrupted by a crash still leaves a trace.
+pub struct AuditLog {
+    conn: Connection,
+}
+
+impl AuditLog {
+    pub fn open(directory: &str) -> Result<AuditLog> {
+        let path = system_path(directory, "audit")?;
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(AuditLog { conn })
+    }
+
+    /// Records the start of an action and returns the ID of the entry.
+    pub fn start(&self, entry: &AuditLogEntry) -> Result<i64> {
+        self.conn.execute(
+            "INSERT INTO audit_log(timestamp, action, repository_id, since, until, reason, requested_by, result)
+            VALUES (?, ?, ?, ?, ?, ?, ?, 'started')",
+            params![
+                entry.timestamp,
+                entry.action,
+                entry.repository_id,
+                entry.since,
+                entry.until,
+                entry.reason,
+                entry.requested_by
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    pub fn finish(&self, id: i64, result: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE audit_log SET result = ? WHERE id = ?",
+            params![result, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_entries(&self, limit: u32) -> Result<Vec<AuditLogEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, timestamp, action, repository_id, since, until, reason, requested_by, result
+            FROM audit_log
+            ORDER BY id DESC
+            LIMIT ?",
+        )?;
+        let entries = stmt
+            .query_map(params![limit], |row| {
+                Ok(AuditLogEntry {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    action: row.get(2)?,
+                    repository_id: row.get(3)?,
+                    since: row.get(4)?,
+                    until: row.get(5)?,
+                    reason: row.get(6)?,
+                    requested_by: row.get(7)?,
+                    result: row.get(8)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+}
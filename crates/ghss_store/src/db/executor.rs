@@ -0,0 +1,146 @@
+use super::{Error, Result};
+use rusqlite::InterruptHandle;
+use std::ops::{Deref, DerefMut};
+use std::panic::AssertUnwindSafe;
+use std::sync::{mpsc, Arc, Mutex};
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Dedicated threads for database work, so slow queries neither block the
+/// async runtime nor compete with its other blocking work.
+pub struct Executor {
+    jobs: Mutex<mpsc::Sender<Job>>,
+}
+
+impl Executor {
+    pub fn new(threads: usize) -> Executor {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("db-{}", index))
+                .spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    // A panicking job drops its reply sender, which the
+                    // caller sees as an error.
+                    let _ = std::panic::catch_unwind(AssertUnwindSafe(job));
+                })
+                .expect("failed to spawn database thread");
+        }
+        Executor {
+            jobs: Mutex::new(sender),
+        }
+    }
+
+    /// Queues the work and returns a receiver for its result.
+    pub fn run<T, F>(&self, work: F) -> oneshot::Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job = Box::new(move || {
+            let _ = sender.send(work());
+        });
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(job)
+            .expect("database threads stopped");
+        receiver
+    }
+}
+
+#[derive(Default)]
+struct InterruptState {
+    interrupted: bool,
+    next_id: u64,
+    handles: Vec<(u64, InterruptHandle)>,
+}
+
+/// Interrupts the statements of one call on all databases it currently uses.
+/// Databases are registered while they are checked out, so returning them to
+/// the pool makes them immune to interrupts of the call.
+#[derive(Default)]
+pub struct Interrupter {
+    state: Mutex<InterruptState>,
+}
+
+impl Interrupter {
+    pub fn interrupt(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.interrupted = true;
+        for (_, handle) in &state.handles {
+            handle.interrupt();
+        }
+    }
+
+    fn register(self: &Arc<Self>, handle: InterruptHandle) -> Result<Registration> {
+        let mut state = self.state.lock().unwrap();
+        if state.interrupted {
+            return Err(Error::Interrupted);
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.handles.push((id, handle));
+        Ok(Registration {
+            interrupter: self.clone(),
+            id,
+        })
+    }
+}
+
+struct Registration {
+    interrupter: Arc<Interrupter>,
+    id: u64,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut state = self.interrupter.state.lock().unwrap();
+        state.handles.retain(|(id, _)| *id != self.id);
+    }
+}
+
+/// Database that can be interrupted by the call using it.
+pub struct Interruptible<T> {
+    // Declared first to unregister before the database is dropped.
+    _registration: Option<Registration>,
+    db: T,
+}
+
+impl<T> Interruptible<T> {
+    pub fn new(
+        db: T,
+        handle: InterruptHandle,
+        interrupter: Option<&Arc<Interrupter>>,
+    ) -> Result<Interruptible<T>> {
+        let registration = match interrupter {
+            Some(interrupter) => Some(interrupter.register(handle)?),
+            None => None,
+        };
+        Ok(Interruptible {
+            _registration: registration,
+            db,
+        })
+    }
+}
+
+impl<T> Deref for Interruptible<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.db
+    }
+}
+
+impl<T> DerefMut for Interruptible<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.db
+    }
+}
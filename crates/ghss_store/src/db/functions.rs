@@ -0,0 +1,156 @@
+use super::sketch::Sketch;
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::{Connection, Error, Result};
+
+fn get_percentile(ctx: &Context<'_>, index: usize) -> Result<f64> {
+    let percentile: f64 = ctx.get(index)?;
+    if !(0.0..=100.0).contains(&percentile) {
+        return Err(Error::UserFunctionError(
+            format!("invalid percentile {}", percentile).into(),
+        ));
+    }
+    Ok(percentile)
+}
+
+fn get_sketch(ctx: &Context<'_>, index: usize) -> Result<Option<Sketch>> {
+    match ctx.get::<Option<Vec<u8>>>(index)? {
+        Some(bytes) => Sketch::from_bytes(&bytes)
+            .map(Some)
+            .ok_or_else(|| Error::UserFunctionError("invalid sketch".into())),
+        None => Ok(None),
+    }
+}
+
+struct Percentile;
+
+struct PercentileState {
+    values: Vec<f64>,
+    percentile: f64,
+}
+
+impl Aggregate<PercentileState, Option<f64>> for Percentile {
+    fn init(&self) -> PercentileState {
+        PercentileState {
+            values: Vec::new(),
+            percentile: 0.0,
+        }
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, state: &mut PercentileState) -> Result<()> {
+        state.percentile = get_percentile(ctx, 1)?;
+        if let Some(value) = ctx.get::<Option<f64>>(0)? {
+            state.values.push(value);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, state: Option<PercentileState>) -> Result<Option<f64>> {
+        let mut state = match state {
+            Some(state) if !state.values.is_empty() => state,
+            _ => return Ok(None),
+        };
+        state
+            .values
+            .sort_unstable_by(|a, b| a.partial_cmp(b).expect("values should not be NaN"));
+
+        // Linear interpolation between the two closest ranks.
+        let rank = state.percentile / 100.0 * (state.values.len() - 1) as f64;
+        let lower = state.values[rank.floor() as usize];
+        let upper = state.values[rank.ceil() as usize];
+        Ok(Some(lower + (upper - lower) * rank.fract()))
+    }
+}
+
+/// `sketch(value)` returns the sketch of all values.
+struct SketchValues;
+
+impl Aggregate<Sketch, Option<Vec<u8>>> for SketchValues {
+    fn init(&self) -> Sketch {
+        Sketch::default()
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, sketch: &mut Sketch) -> Result<()> {
+        if let Some(value) = ctx.get::<Option<f64>>(0)? {
+            sketch.add(value);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, sketch: Option<Sketch>) -> Result<Option<Vec<u8>>> {
+        Ok(sketch.map(|sketch| sketch.to_bytes()))
+    }
+}
+
+/// `sketch_merge(sketch)` merges sketches.
+struct SketchMerge;
+
+impl Aggregate<Sketch, Option<Vec<u8>>> for SketchMerge {
+    fn init(&self) -> Sketch {
+        Sketch::default()
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, sketch: &mut Sketch) -> Result<()> {
+        if let Some(other) = get_sketch(ctx, 0)? {
+            sketch.merge(&other);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, sketch: Option<Sketch>) -> Result<Option<Vec<u8>>> {
+        Ok(sketch.map(|sketch| sketch.to_bytes()))
+    }
+}
+
+/// `sketch_percentile(sketch, percentile)` merges sketches and estimates the
+/// percentile of their values.
+struct SketchPercentile;
+
+impl Aggregate<(Sketch, f64), Option<f64>> for SketchPercentile {
+    fn init(&self) -> (Sketch, f64) {
+        (Sketch::default(), 0.0)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, state: &mut (Sketch, f64)) -> Result<()> {
+        state.1 = get_percentile(ctx, 1)?;
+        if let Some(other) = get_sketch(ctx, 0)? {
+            state.0.merge(&other);
+        }
+        Ok(())
+    }
+
+    fn finalize(&self, state: Option<(Sketch, f64)>) -> Result<Option<f64>> {
+        Ok(state.and_then(|(sketch, percentile)| sketch.percentile(percentile)))
+    }
+}
+
+/// Registers `interval_start(timestamp)`, which maps a timestamp to the
+/// closest interval start before or at it. `starts` has to be sorted.
+pub fn register_interval_start(conn: &Connection, starts: Vec<i64>) -> Result<()> {
+    conn.create_scalar_function(
+        "interval_start",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let timestamp: i64 = ctx.get(0)?;
+            Ok(match starts.binary_search(&timestamp) {
+                Ok(i) => Some(starts[i]),
+                Err(0) => None,
+                Err(i) => Some(starts[i - 1]),
+            })
+        },
+    )
+}
+
+pub fn register(conn: &Connection) -> Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_aggregate_function("percentile", 2, flags, Percentile)?;
+    conn.create_aggregate_function("sketch", 1, flags, SketchValues)?;
+    conn.create_aggregate_function("sketch_merge", 1, flags, SketchMerge)?;
+    conn.create_aggregate_function("sketch_percentile", 2, flags, SketchPercentile)?;
+    // `sketch_value(value)` returns the sketch of a single value.
+    conn.create_scalar_function("sketch_value", 1, flags, |ctx| {
+        let mut sketch = Sketch::default();
+        sketch.add(ctx.get(0)?);
+        Ok(sketch.to_bytes())
+    })
+}
@@ -0,0 +1,111 @@
+use super::{Error, Result};
+use crate::proto::IntervalType;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use chrono_tz::Tz;
+
+const MAX_INTERVALS: usize = 10_000;
+
+pub fn parse_timezone(timezone: &str) -> Result<Tz> {
+    if timezone.is_empty() {
+        return Ok(Tz::UTC);
+    }
+
+    timezone
+        .parse()
+        .map_err(|_| Error::InvalidTimezone(timezone.into()))
+}
+
+fn truncate(interval_type: IntervalType, local: NaiveDateTime) -> NaiveDateTime {
+    let date = local.date();
+    match interval_type {
+        IntervalType::Hour => date.and_hms(local.hour(), 0, 0),
+        IntervalType::Day => date.and_hms(0, 0, 0),
+        IntervalType::Week => {
+            (date - Duration::days(date.weekday().num_days_from_monday().into())).and_hms(0, 0, 0)
+        }
+        IntervalType::Month => NaiveDate::from_ymd(date.year(), date.month(), 1).and_hms(0, 0, 0),
+        IntervalType::Sparse | IntervalType::Detailed => unreachable!("not a calendar interval"),
+    }
+}
+
+fn next(interval_type: IntervalType, local: NaiveDateTime) -> NaiveDateTime {
+    match interval_type {
+        IntervalType::Day => local + Duration::days(1),
+        IntervalType::Week => local + Duration::weeks(1),
+        IntervalType::Month => {
+            let date = local.date();
+            let (year, month) = if date.month() == 12 {
+                (date.year() + 1, 1)
+            } else {
+                (date.year(), date.month() + 1)
+            };
+            NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0)
+        }
+        IntervalType::Hour | IntervalType::Sparse | IntervalType::Detailed => {
+            unreachable!("not a calendar day interval")
+        }
+    }
+}
+
+fn local_to_millis(tz: Tz, local: NaiveDateTime) -> i64 {
+    // Boundaries falling into a DST gap don't exist in local time. Use the
+    // first valid time after the gap instead.
+    tz.from_local_datetime(&local)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|datetime| datetime.timestamp_millis())
+        .unwrap_or_else(|| local.timestamp_millis())
+}
+
+/// Returns the start timestamps (in milliseconds) of all calendar intervals
+/// overlapping the time range `from..=to`, in ascending order.
+pub fn calendar_intervals(
+    interval_type: IntervalType,
+    tz: Tz,
+    from: i64,
+    to: i64,
+) -> Result<Vec<i64>> {
+    let datetime = |millis: i64| {
+        NaiveDateTime::from_timestamp_opt(millis.div_euclid(1000), 0).ok_or(Error::InvalidTimeRange)
+    };
+    let from_utc = datetime(from)?;
+    datetime(to)?;
+    let mut local = truncate(interval_type, tz.from_utc_datetime(&from_utc).naive_local());
+    let mut start = local_to_millis(tz, local);
+    let mut intervals = Vec::new();
+    while start <= to {
+        if intervals.len() == MAX_INTERVALS {
+            return Err(Error::TooManyIntervals);
+        }
+        intervals.push(start);
+        start = match interval_type {
+            // Hours are counted in absolute time, so that both occurrences of
+            // the repeated hour at the end of DST get their own interval.
+            IntervalType::Hour => start + 60 * 60 * 1000,
+            _ => {
+                local = next(interval_type, local);
+                local_to_millis(tz, local)
+            }
+        };
+    }
+
+    Ok(intervals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_out_of_range_timestamps() {
+        for (from, to) in &[(i64::MIN, 0), (0, i64::MAX)] {
+            match calendar_intervals(IntervalType::Day, Tz::UTC, *from, *to) {
+                Err(Error::InvalidTimeRange) => {}
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+}
@@ -1,31 +1,173 @@
-pub mod read;
-mod schema;
-pub mod write;
-
-use std::convert::From;
-
-#[derive(Debug)]
-pub enum Error {
-    DBNotFound,
-    InvalidIdentifier(String),
-    EmptyColumns,
-    InvalidTimeRange,
-    SQLite(rusqlite::Error),
-}
-
-impl From<rusqlite::Error> for Error {
-    fn from(err: rusqlite::Error) -> Self {
-        match err {
-            rusqlite::Error::SqliteFailure(
-                rusqlite::ffi::Error {
-                    code: rusqlite::ErrorCode::CannotOpen,
-                    extended_code: _,
-                },
-                _,
-            ) => Error::DBNotFound,
-            _ => Error::SQLite(err),
-        }
-    }
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
+pub mod audit;
+pub mod executor;
+mod functions;
+mod intervals;
+pub mod pool;
+pub mod read;
+pub mod registry;
+mod rollups;
+mod schema;
+mod sketch;
+pub mod write;
+
+use std::convert::From;
+
+#[derive(Debug)]
+pub enum Error {
+    DBNotFound,
+    InvalidIdentifier(String),
+    InvalidRepositoryId(String),
+    EmptyColumns,
+    MissingAuditInfo,
+    IncompleteImport,
+    ImportTooLarge,
+    InvalidTimeRange,
+    InvalidPercentile(f64),
+    InvalidFilter(String),
+    InvalidTimezone(String),
+    TooManyIntervals,
+    UnsupportedSchemaVersion(u32),
+    NoImport,
+    RepositoryInactive,
+    RepositoryPurged,
+    Interrupted,
+    SQLite(rusqlite::Error),
+    IO(std::io::Error),
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        match err {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::CannotOpen,
+                    extended_code: _,
+                },
+                _,
+            ) => Error::DBNotFound,
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::OperationInterrupted,
+                    extended_code: _,
+                },
+                _,
+            ) => Error::Interrupted,
+            _ => Error::SQLite(err),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Subdirectory of the database directory with the databases shared by all
+/// repositories, so they can't be mistaken for repository databases.
+const SYSTEM_DIRECTORY: &str = "system";
+
+/// Names of the shared databases, which were kept next to the repository
+/// databases before.
+const RESERVED_REPOSITORY_IDS: &[&str] = &["registry", "audit"];
+
+/// Checks that a repository ID can be used as a file name in the database
+/// directory.
+pub fn validate_repository_id(repository_id: &str) -> Result<()> {
+    let valid = !repository_id.is_empty()
+        && !repository_id.starts_with('.')
+        && !RESERVED_REPOSITORY_IDS.contains(&repository_id)
+        && repository_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    if !valid {
+        return Err(Error::InvalidRepositoryId(repository_id.to_owned()));
+    }
+    Ok(())
+}
+
+/// Returns the path of a file of a repository database.
+fn repository_path(directory: &str, repository_id: &str, suffix: &str) -> Result<String> {
+    validate_repository_id(repository_id)?;
+    Ok(format!("{}/{}.{}", directory, repository_id, suffix))
+}
+
+/// Returns the path of a shared database, creating its directory and moving
+/// a database left in the old location.
+fn system_path(directory: &str, name: &str) -> Result<String> {
+    let system_directory = format!("{}/{}", directory, SYSTEM_DIRECTORY);
+    std::fs::create_dir_all(&system_directory).map_err(Error::IO)?;
+    let path = format!("{}/{}.db", system_directory, name);
+    if std::path::Path::new(&path).exists() {
+        return Ok(path);
+    }
+    for suffix in &["db", "db-wal", "db-shm"] {
+        let old_path = format!("{}/{}.{}", directory, name, suffix);
+        let new_path = format!("{}/{}.{}", system_directory, name, suffix);
+        match std::fs::rename(old_path, new_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(Error::IO(err)),
+        }
+    }
+    Ok(path)
+}
+
+/// Deletes the database of a repository including its write-ahead log.
+pub fn delete(directory: &str, repository_id: &str) -> Result<()> {
+    for suffix in &["db", "db-wal", "db-shm"] {
+        let path = repository_path(directory, repository_id, suffix)?;
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(Error::IO(err)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_repository_ids() {
+        for repository_id in &["123", "ghe-123", "github.example.com-123", "a_b"] {
+            assert!(
+                validate_repository_id(repository_id).is_ok(),
+                "{}",
+                repository_id
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_repository_ids_that_escape_the_database_directory() {
+        for repository_id in &[
+            "", ".", "..", "../123", "a/b", "a\\b", ".hidden", "registry", "audit", "a b", "a\0",
+        ] {
+            assert!(
+                matches!(
+                    validate_repository_id(repository_id),
+                    Err(Error::InvalidRepositoryId(_))
+                ),
+                "{:?}",
+                repository_id
+            );
+        }
+        assert!(repository_path("/data", "../x", "db").is_err());
+        assert!(delete("/data", "../x").is_err());
+    }
+
+    #[test]
+    fn moves_system_databases_into_their_directory() {
+        let directory = std::env::temp_dir().join(format!("ghss-system-{}", std::process::id()));
+        let directory = directory.to_str().unwrap();
+        std::fs::create_dir_all(directory).unwrap();
+        std::fs::write(format!("{}/registry.db", directory), "registry").unwrap();
+
+        let path = system_path(directory, "registry").unwrap();
+        assert_eq!(path, format!("{}/system/registry.db", directory));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "registry");
+        assert!(!std::path::Path::new(&format!("{}/registry.db", directory)).exists());
+        assert_eq!(system_path(directory, "registry").unwrap(), path);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use super::Result;
+use ghss_tracing::log_event;
+use opentelemetry::api::{Context, Key, TraceContextExt, Tracer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+struct Idle<T> {
+    repository_id: String,
+    db: T,
+    returned_at: Instant,
+}
+
+struct State<T> {
+    // Least recently returned first.
+    idle: VecDeque<Idle<T>>,
+    // Repositories with a database checked out. Only tracked by exclusive
+    // pools.
+    checked_out: HashSet<String>,
+    // Number of databases checked out.
+    in_use: usize,
+    // Incremented whenever the databases of a repository are evicted, so
+    // handles checked out before aren't returned to the pool.
+    generations: HashMap<String, u64>,
+}
+
+impl<T> State<T> {
+    fn generation(&self, repository_id: &str) -> u64 {
+        self.generations
+            .get(repository_id)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Bounded pool of open repository databases. Databases are checked out for
+/// exclusive use and returned when the `Pooled` handle is dropped. Checked
+/// out databases count against the capacity. Once the pool is full, the least
+/// recently returned database is closed. Checking out never waits for
+/// capacity, the number of database threads bounds the databases in use.
+pub struct Pool<T> {
+    name: &'static str,
+    capacity: usize,
+    // At most one database per repository is open if set.
+    exclusive: bool,
+    state: Mutex<State<T>>,
+    returned: Condvar,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<T> Pool<T> {
+    pub fn new(name: &'static str, capacity: usize) -> Arc<Pool<T>> {
+        Pool::with_exclusive(name, capacity, false)
+    }
+
+    /// Pool with at most one database per repository, e.g. for writers,
+    /// which SQLite doesn't allow to write concurrently. Checking out a
+    /// database waits until the previous one of the repository is returned.
+    pub fn exclusive(name: &'static str, capacity: usize) -> Arc<Pool<T>> {
+        Pool::with_exclusive(name, capacity, true)
+    }
+
+    fn with_exclusive(name: &'static str, capacity: usize, exclusive: bool) -> Arc<Pool<T>> {
+        Arc::new(Pool {
+            name,
+            capacity,
+            exclusive,
+            state: Mutex::new(State {
+                idle: VecDeque::with_capacity(capacity),
+                checked_out: HashSet::new(),
+                in_use: 0,
+                generations: HashMap::new(),
+            }),
+            returned: Condvar::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        })
+    }
+
+    /// Checks out an idle database of the repository or opens a new one.
+    pub fn get(
+        self: &Arc<Self>,
+        repository_id: &str,
+        open: impl FnOnce() -> Result<T>,
+    ) -> Result<Pooled<T>> {
+        let (idle, generation) = {
+            let mut state = self.state.lock().unwrap();
+            if self.exclusive {
+                while state.checked_out.contains(repository_id) {
+                    state = self.returned.wait(state).unwrap();
+                }
+                state.checked_out.insert(repository_id.to_owned());
+            }
+            state.in_use += 1;
+            let idle = state
+                .idle
+                .iter()
+                .rposition(|idle| idle.repository_id == repository_id)
+                .and_then(|index| state.idle.remove(index));
+            if idle.is_none() {
+                self.trim(&mut state);
+            }
+            (idle, state.generation(repository_id))
+        };
+        let hit = idle.is_some();
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        Context::current()
+            .span()
+            .set_attribute(Key::new(format!("db.pool.{}.hit", self.name)).bool(hit));
+        let db = match idle {
+            Some(idle) => idle.db,
+            None => match open() {
+                Ok(db) => db,
+                Err(err) => {
+                    self.release(&mut self.state.lock().unwrap(), repository_id);
+                    return Err(err);
+                }
+            },
+        };
+        Ok(Pooled {
+            pool: self.clone(),
+            repository_id: repository_id.to_owned(),
+            db: Some(db),
+            generation,
+        })
+    }
+
+    /// Lets the next caller check out a database of the repository.
+    fn release(&self, state: &mut State<T>, repository_id: &str) {
+        state.in_use -= 1;
+        if state.checked_out.remove(repository_id) {
+            self.returned.notify_all();
+        }
+    }
+
+    /// Closes the least recently returned databases until the open ones fit
+    /// into the capacity.
+    fn trim(&self, state: &mut State<T>) {
+        while !state.idle.is_empty() && state.idle.len() + state.in_use > self.capacity {
+            state.idle.pop_front();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn put(&self, repository_id: String, db: T, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        self.release(&mut state, &repository_id);
+        if generation != state.generation(&repository_id) {
+            return;
+        }
+        state.idle.push_back(Idle {
+            repository_id,
+            db,
+            returned_at: Instant::now(),
+        });
+        self.trim(&mut state);
+    }
+
+    /// Closes all databases of a repository, including the ones currently
+    /// checked out once they are returned. Required before the database
+    /// files are deleted.
+    pub fn evict(&self, repository_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        *state
+            .generations
+            .entry(repository_id.to_owned())
+            .or_default() += 1;
+        state
+            .idle
+            .retain(|idle| idle.repository_id != repository_id);
+    }
+
+    /// Closes databases that weren't used for longer than `max_idle`.
+    fn evict_idle(&self, max_idle: Duration) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let idle = &mut state.idle;
+        let len = idle.len();
+        while let Some(oldest) = idle.front() {
+            if oldest.returned_at.elapsed() <= max_idle {
+                break;
+            }
+            idle.pop_front();
+        }
+        let evicted = len - idle.len();
+        self.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    fn record_stats(&self) {
+        let cx = Context::current();
+        let span = cx.span();
+        let (idle, in_use) = {
+            let state = self.state.lock().unwrap();
+            (state.idle.len() as u64, state.in_use as u64)
+        };
+        for (stat, value) in &[
+            ("idle", idle),
+            ("in_use", in_use),
+            ("hits", self.hits.load(Ordering::Relaxed)),
+            ("misses", self.misses.load(Ordering::Relaxed)),
+            ("evictions", self.evictions.load(Ordering::Relaxed)),
+        ] {
+            span.set_attribute(Key::new(format!("db.pool.{}.{}", self.name, stat)).u64(*value));
+        }
+    }
+}
+
+/// Database checked out of a `Pool`.
+pub struct Pooled<T> {
+    pool: Arc<Pool<T>>,
+    repository_id: String,
+    db: Option<T>,
+    generation: u64,
+}
+
+impl<T> Deref for Pooled<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.db.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for Pooled<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.db.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for Pooled<T> {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.pool
+                .put(std::mem::take(&mut self.repository_id), db, self.generation);
+        }
+    }
+}
+
+/// Closes databases of the pools that weren't used for longer than
+/// `max_idle` and records statistics of the pools. Runs forever.
+pub async fn evict_idle_databases<R, W>(
+    readers: Arc<Pool<R>>,
+    writers: Arc<Pool<W>>,
+    max_idle: Duration,
+) {
+    let tracer = opentelemetry::global::tracer("store");
+    let mut interval = tokio::time::interval(max_idle / 2);
+    loop {
+        interval.tick().await;
+        let span = tracer.start("evict idle databases");
+        let cx = Context::current_with_span(span);
+        let _guard = cx.attach();
+        let evicted = readers.evict_idle(max_idle) + writers.evict_idle(max_idle);
+        log_event(format!("evicted {} databases", evicted));
+        readers.record_stats();
+        writers.record_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Error;
+    use std::sync::mpsc;
+
+    #[test]
+    fn exclusive_pool_waits_for_returned_database() {
+        let pool = Pool::exclusive("test", 4);
+        let db = pool.get("1", || Ok(1)).unwrap();
+        // Other repositories aren't blocked.
+        pool.get("2", || Ok(2)).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let waiting = std::thread::spawn({
+            let pool = pool.clone();
+            move || {
+                let db = pool.get("1", || Ok(3)).unwrap();
+                sender.send(*db).unwrap();
+            }
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(db);
+        assert_eq!(receiver.recv().unwrap(), 1);
+        waiting.join().unwrap();
+    }
+
+    #[test]
+    fn exclusive_pool_releases_failed_open() {
+        let pool = Pool::<i32>::exclusive("test", 4);
+        assert!(pool.get("1", || Err(Error::DBNotFound)).is_err());
+        assert_eq!(*pool.get("1", || Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn evicts_only_databases_of_the_repository() {
+        let pool = Pool::new("test", 4);
+        let db1 = pool.get("1", || Ok(1)).unwrap();
+        let db2 = pool.get("2", || Ok(2)).unwrap();
+        pool.evict("1");
+        drop(db1);
+        drop(db2);
+        assert_eq!(*pool.get("1", || Ok(3)).unwrap(), 3);
+        assert_eq!(*pool.get("2", || Ok(4)).unwrap(), 2);
+    }
+
+    #[test]
+    fn counts_checked_out_databases_against_capacity() {
+        let pool = Pool::new("test", 2);
+        drop(pool.get("1", || Ok(1)).unwrap());
+        drop(pool.get("2", || Ok(2)).unwrap());
+        let _db3 = pool.get("3", || Ok(3)).unwrap();
+        // Only one database of the others can stay open next to the checked
+        // out one, the least recently returned is closed.
+        assert_eq!(*pool.get("2", || Ok(5)).unwrap(), 2);
+        assert_eq!(*pool.get("1", || Ok(4)).unwrap(), 4);
+    }
+}
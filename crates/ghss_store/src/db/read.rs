@@ -1,229 +1,805 @@
-use super::{Error, Result};
-use crate::proto::{
-    interval_aggregates_reply, total_aggregates_reply, AggregateFunction, Column, HookedCommit,
-    IntervalAggregatesReply, IntervalType, TotalAggregatesReply,
-};
-use ghss_tracing::log_event;
-use rusqlite::{params, Connection, OpenFlags};
-
-pub struct DB {
-    conn: Connection,
-}
-
-fn validate_identifier(s: &str) -> Result<()> {
-    if s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
-        Ok(())
-    } else {
-        Err(Error::InvalidIdentifier(s.into()))
-    }
-}
-
-fn create_group_by(group_by_columns: Vec<String>) -> Result<Vec<String>> {
-    group_by_columns
-        .iter()
-        .map(|c| {
-            validate_identifier(&c)?;
-            Ok(format!("\"{}\"", c))
-        })
-        .collect()
-}
-
-fn create_projection(columns: Vec<Column>, group_by: Vec<String>) -> Vec<String> {
-    let mut projection = columns
-        .iter()
-        .map(|c| {
-            let agg = match c.agg_func() {
-                AggregateFunction::Avg => "avg",
-                AggregateFunction::Count => "count",
-            };
-            format!("{}(\"{}\")", agg, c.name)
-        })
-        .collect::<Vec<_>>();
-    if !group_by.is_empty() {
-        projection.extend(group_by);
-    }
-
-    projection
-}
-
-fn create_aggregate_query_sql(
-    projection: Vec<String>,
-    table: String,
-    from: i64,
-    to: i64,
-    group_by: Vec<String>,
-    order_by: Option<&'static str>,
-) -> String {
-    let mut sql = format!(
-        "SELECT {} FROM {} WHERE timestamp >= {} AND timestamp <= {}",
-        projection.join(", "),
-        table,
-        from,
-        to
-    );
-    if !group_by.is_empty() {
-        sql.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
-    }
-    if let Some(order_by) = order_by {
-        sql.push_str(&format!(" ORDER BY {}", order_by));
-    }
-
-    sql
-}
-
-impl DB {
-    pub fn open(directory: &str, repository_id: &str) -> Result<DB> {
-        let path = format!("{}/{}.db", directory, repository_id);
-        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
-        Ok(DB { conn })
-    }
-
-    pub fn get_hooked_commits_since_last_import(&self, until: i64) -> Result<Vec<HookedCommit>> {
-        let mut stmt = self.conn.prepare(
-            "WITH last_import AS (SELECT timestamp FROM imports ORDER BY timestamp DESC LIMIT 1)
-            SELECT \"commit\", group_concat(type) AS types
-            FROM hooks
-            WHERE timestamp > (SELECT timestamp FROM last_import) AND timestamp <= ?
-            GROUP BY \"commit\"",
-        )?;
-        let hooked_commits = stmt
-            .query_map(params![until], |row| {
-                let commit = row.get(0)?;
-                let types_comma_separated: String = row.get(1)?;
-                let types = types_comma_separated
-                    .split(',')
-                    .map(|s| s.parse().unwrap())
-                    .collect();
-                Ok(HookedCommit { commit, types })
-            })?
-            .map(|row| row.map_err(|err| err.into()))
-            .collect::<Result<_>>()?;
-        Ok(hooked_commits)
-    }
-
-    pub fn get_total_aggregates(
-        &self,
-        table: String,
-        columns: Vec<Column>,
-        from: i64,
-        to: i64,
-        group_by_columns: Vec<String>,
-    ) -> Result<TotalAggregatesReply> {
-        validate_identifier(&table)?;
-        if columns.is_empty() {
-            return Err(Error::EmptyColumns);
-        }
-
-        let group_by = create_group_by(group_by_columns)?;
-
-        let values_range = 0..columns.len();
-        let groups_range = values_range.end..values_range.end + group_by.len();
-
-        let projection = create_projection(columns, group_by.clone());
-        let is_grouped = !group_by.is_empty();
-        let sql = create_aggregate_query_sql(projection, table, from, to, group_by, None);
-
-        log_event(format!("sql: {}", sql));
-
-        let mut stmt = self.conn.prepare(&sql)?;
-        let rows = if is_grouped {
-            stmt.query_map(params![], |row| {
-                Ok(total_aggregates_reply::Row {
-                    values: values_range
-                        .clone()
-                        .map(|i| row.get(i))
-                        .collect::<rusqlite::Result<_>>()?,
-                    groups: groups_range
-                        .clone()
-                        .map(|i| row.get(i))
-                        .collect::<rusqlite::Result<_>>()?,
-                })
-            })?
-            .map(|row| row.map_err(|err| err.into()))
-            .collect::<Result<_>>()?
-        } else {
-            // Without a GROUP BY clause SQLite always returns exactly 1 row.
-            // If not rows match the WHERE clause, some aggregate functions
-            // like avg() or max() return NULL.
-            stmt.query_row(params![], |row| {
-                let values: Vec<Option<f64>> = values_range
-                    .clone()
-                    .map(|i| row.get(i))
-                    .collect::<rusqlite::Result<_>>()?;
-                let rows = if values.iter().any(Option::is_none) {
-                    Vec::new()
-                } else {
-                    vec![total_aggregates_reply::Row {
-                        values: values.into_iter().map(Option::unwrap).collect(),
-                        groups: Vec::new(),
-                    }]
-                };
-                Ok(rows)
-            })?
-        };
-
-        Ok(TotalAggregatesReply { rows })
-    }
-
-    pub fn get_interval_aggregates(
-        &self,
-        table: String,
-        columns: Vec<Column>,
-        from: i64,
-        to: i64,
-        group_by_columns: Vec<String>,
-        interval_type: IntervalType,
-    ) -> Result<IntervalAggregatesReply> {
-        validate_identifier(&table)?;
-        if columns.is_empty() {
-            return Err(Error::EmptyColumns);
-        }
-
-        let mut group_by = create_group_by(group_by_columns)?;
-
-        let values_range = 0..columns.len();
-        let groups_range = values_range.end..values_range.end + group_by.len();
-        let timestamp_index = groups_range.end;
-
-        let mut projection = create_projection(columns, group_by.clone());
-
-        group_by.push("interval".into());
-        let time_range = to - from;
-        if time_range <= 0 {
-            return Err(Error::InvalidTimeRange);
-        }
-        let interval = match interval_type {
-            IntervalType::Sparse => time_range / 120,
-            IntervalType::Detailed => time_range / 720,
-        };
-        projection.push(format!(
-            "timestamp / {} * {} AS interval",
-            interval, interval
-        ));
-
-        let sql =
-            create_aggregate_query_sql(projection, table, from, to, group_by, Some("interval"));
-
-        log_event(format!("sql: {}", sql));
-
-        let mut stmt = self.conn.prepare(&sql)?;
-        let rows = stmt
-            .query_map(params![], |row| {
-                Ok(interval_aggregates_reply::Row {
-                    values: values_range
-                        .clone()
-                        .map(|i| row.get(i))
-                        .collect::<rusqlite::Result<_>>()?,
-                    groups: groups_range
-                        .clone()
-                        .map(|i| row.get(i))
-                        .collect::<rusqlite::Result<_>>()?,
-                    timestamp: row.get(timestamp_index)?,
-                })
-            })?
-            .map(|row| row.map_err(|err| err.into()))
-            .collect::<Result<_>>()?;
-
-        Ok(IntervalAggregatesReply { rows })
-    }
-}
+use super::{functions, intervals, repository_path, rollups, schema};
+use super::{Error, Result};
+use crate::proto::{
+    filter, flaky_builds_reply, interval_aggregates_reply, total_aggregates_reply, value,
+    AggregateFunction, BackfillState, Column, ComparisonOperator, Filter, FlakyBuildsReply,
+    FlakyBuildsRequest, HookedCommit, IntervalAggregatesReply, IntervalAggregatesRequest,
+    IntervalType, TotalAggregatesReply, TotalAggregatesRequest, Value,
+};
+use ghss_tracing::log_event;
+use rusqlite::{
+    params, types, Connection, InterruptHandle, OpenFlags, OptionalExtension, NO_PARAMS,
+};
+
+// Maximum nesting of AND and OR filters.
+const MAX_FILTER_DEPTH: usize = 16;
+
+pub struct DB {
+    conn: Connection,
+}
+
+fn validate_identifier(s: &str) -> Result<()> {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(())
+    } else {
+        Err(Error::InvalidIdentifier(s.into()))
+    }
+}
+
+fn create_group_by(group_by_columns: Vec<String>) -> Result<Vec<String>> {
+    group_by_columns
+        .iter()
+        .map(|c| {
+            validate_identifier(&c)?;
+            Ok(format!("\"{}\"", c))
+        })
+        .collect()
+}
+
+// Creates a SQL expression for the aggregate. The percentile is not formatted
+// into the expression but added to `params`.
+fn create_aggregate(column: &Column, params: &mut Vec<types::Value>) -> Result<String> {
+    validate_identifier(&column.name)?;
+    let aggregate = match column.agg_func() {
+        AggregateFunction::Avg => format!("avg(\"{}\")", column.name),
+        AggregateFunction::Count => format!("count(\"{}\")", column.name),
+        AggregateFunction::Min => format!("min(\"{}\")", column.name),
+        AggregateFunction::Max => format!("max(\"{}\")", column.name),
+        AggregateFunction::Sum => format!("sum(\"{}\")", column.name),
+        AggregateFunction::Percentile => {
+            if !(0.0..=100.0).contains(&column.percentile) {
+                return Err(Error::InvalidPercentile(column.percentile));
+            }
+            params.push(types::Value::Real(column.percentile));
+            format!("percentile(\"{}\", ?)", column.name)
+        }
+    };
+    Ok(aggregate)
+}
+
+fn create_aggregates(columns: &[Column], params: &mut Vec<types::Value>) -> Result<Vec<String>> {
+    columns
+        .iter()
+        .map(|column| create_aggregate(column, params))
+        .collect()
+}
+
+fn create_projection(
+    columns: &[Column],
+    group_by: Vec<String>,
+    params: &mut Vec<types::Value>,
+) -> Result<Vec<String>> {
+    let aggregates = create_aggregates(columns, params)?;
+    Ok(create_projection_with(aggregates, group_by))
+}
+
+fn create_projection_with(aggregates: Vec<String>, group_by: Vec<String>) -> Vec<String> {
+    let mut projection = aggregates;
+    // Groups are returned as strings, but may be grouped by non-text columns
+    // like source or pull_request.
+    projection.extend(
+        group_by
+            .into_iter()
+            .map(|column| format!("CAST({} AS TEXT)", column)),
+    );
+
+    projection
+}
+
+fn create_filter_value(value: Option<&Value>) -> Result<types::Value> {
+    match value.and_then(|value| value.kind.as_ref()) {
+        Some(value::Kind::Text(v)) => Ok(types::Value::Text(v.clone())),
+        Some(value::Kind::Integer(v)) => Ok(types::Value::Integer(*v)),
+        Some(value::Kind::Real(v)) => Ok(types::Value::Real(*v)),
+        None => Err(Error::InvalidFilter("missing value".into())),
+    }
+}
+
+fn validate_filter_column(column: &str, columns: &[String]) -> Result<()> {
+    validate_identifier(column)?;
+    if columns.iter().any(|c| c == column) {
+        Ok(())
+    } else {
+        Err(Error::InvalidFilter(format!("unknown column {}", column)))
+    }
+}
+
+fn create_filter_list(
+    filters: &[Filter],
+    operator: &str,
+    columns: &[String],
+    depth: usize,
+    params: &mut Vec<types::Value>,
+) -> Result<String> {
+    if filters.is_empty() {
+        return Err(Error::InvalidFilter(format!("empty {} list", operator)));
+    }
+    if depth >= MAX_FILTER_DEPTH {
+        return Err(Error::InvalidFilter("too deeply nested".into()));
+    }
+
+    let expressions = filters
+        .iter()
+        .map(|filter| create_filter(filter, columns, depth + 1, params))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(format!(
+        "({})",
+        expressions.join(&format!(" {} ", operator))
+    ))
+}
+
+// Creates a SQL expression for the filter. Values are not formatted into the
+// expression but added to `params` in the order of their placeholders. Only
+// the given columns of the table can be filtered.
+fn create_filter(
+    filter: &Filter,
+    columns: &[String],
+    depth: usize,
+    params: &mut Vec<types::Value>,
+) -> Result<String> {
+    match &filter.expr {
+        Some(filter::Expr::Comparison(comparison)) => {
+            validate_filter_column(&comparison.column, columns)?;
+            let op = match comparison.op() {
+                ComparisonOperator::Eq => "=",
+                ComparisonOperator::Ne => "!=",
+                ComparisonOperator::Lt => "<",
+                ComparisonOperator::Le => "<=",
+                ComparisonOperator::Gt => ">",
+                ComparisonOperator::Ge => ">=",
+            };
+            params.push(create_filter_value(comparison.value.as_ref())?);
+            Ok(format!("\"{}\" {} ?", comparison.column, op))
+        }
+        Some(filter::Expr::InList(in_list)) => {
+            validate_filter_column(&in_list.column, columns)?;
+            if in_list.values.is_empty() {
+                return Err(Error::InvalidFilter("empty IN list".into()));
+            }
+            for value in &in_list.values {
+                params.push(create_filter_value(Some(value))?);
+            }
+            let placeholders = vec!["?"; in_list.values.len()];
+            Ok(format!(
+                "\"{}\" IN ({})",
+                in_list.column,
+                placeholders.join(", ")
+            ))
+        }
+        Some(filter::Expr::And(list)) => {
+            create_filter_list(&list.filters, "AND", columns, depth, params)
+        }
+        Some(filter::Expr::Or(list)) => {
+            create_filter_list(&list.filters, "OR", columns, depth, params)
+        }
+        None => Err(Error::InvalidFilter("missing expression".into())),
+    }
+}
+
+fn create_aggregate_query_sql(
+    projection: Vec<String>,
+    table: String,
+    filter: Option<String>,
+    group_by: Vec<String>,
+    order_by: Option<&'static str>,
+) -> String {
+    let mut sql = format!(
+        "SELECT {} FROM {} WHERE timestamp >= ? AND timestamp <= ?",
+        projection.join(", "),
+        table,
+    );
+    if let Some(filter) = filter {
+        sql.push_str(&format!(" AND {}", filter));
+    }
+    if !group_by.is_empty() {
+        sql.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
+    }
+    if let Some(order_by) = order_by {
+        sql.push_str(&format!(" ORDER BY {}", order_by));
+    }
+
+    sql
+}
+
+fn fill_empty_intervals(
+    rows: Vec<interval_aggregates_reply::Row>,
+    starts: Vec<i64>,
+) -> Vec<interval_aggregates_reply::Row> {
+    let mut rows = rows.into_iter().peekable();
+    let mut filled = Vec::new();
+    for start in starts {
+        if rows.peek().map(|row| row.timestamp) != Some(start) {
+            filled.push(interval_aggregates_reply::Row {
+                values: Vec::new(),
+                groups: Vec::new(),
+                timestamp: start,
+            });
+        }
+        while rows.peek().map(|row| row.timestamp) == Some(start) {
+            filled.extend(rows.next());
+        }
+    }
+
+    filled
+}
+
+impl DB {
+    pub fn open(directory: &str, repository_id: &str) -> Result<DB> {
+        let path = repository_path(directory, repository_id, "db")?;
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        schema::version(&conn)?;
+        functions::register(&conn)?;
+        Ok(DB { conn })
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.conn.get_interrupt_handle()
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT name FROM pragma_table_info(?)")?;
+        let columns = stmt
+            .query_map(params![table], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(columns)
+    }
+
+    fn create_filter(
+        &self,
+        table: &str,
+        filter: &Filter,
+        params: &mut Vec<types::Value>,
+    ) -> Result<String> {
+        let columns = self.table_columns(table)?;
+        create_filter(filter, &columns, 0, params)
+    }
+
+    pub fn get_hooked_commits_since_last_import(&self, until: i64) -> Result<Vec<HookedCommit>> {
+        // Hooks may be recorded before the first import, which has to import
+        // the most recent commits instead.
+        let imported: bool =
+            self.conn
+                .query_row("SELECT EXISTS (SELECT 1 FROM imports)", NO_PARAMS, |row| {
+                    row.get(0)
+                })?;
+        if !imported {
+            return Err(Error::NoImport);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "WITH last_import AS (SELECT timestamp FROM imports ORDER BY timestamp DESC LIMIT 1)
+            SELECT \"commit\", group_concat(type) AS types, max(branch), max(pull_request)
+            FROM hooks
+            WHERE timestamp > (SELECT timestamp FROM last_import) AND timestamp <= ?
+            GROUP BY \"commit\"",
+        )?;
+        let hooked_commits = stmt
+            .query_map(params![until], |row| {
+                let commit = row.get(0)?;
+                let types_comma_separated: String = row.get(1)?;
+                let types = types_comma_separated
+                    .split(',')
+                    .map(|s| s.parse().unwrap())
+                    .collect();
+                Ok(HookedCommit {
+                    commit,
+                    types,
+                    branch: row.get(2)?,
+                    pull_request: row.get(3)?,
+                })
+            })?
+            .map(|row| row.map_err(|err| err.into()))
+            .collect::<Result<_>>()?;
+        Ok(hooked_commits)
+    }
+
+    pub fn get_backfill_state(&self) -> Result<Option<BackfillState>> {
+        let state = self
+            .conn
+            .query_row(
+                "SELECT head, branch, cursor, commits, completed FROM backfill WHERE id = 0",
+                NO_PARAMS,
+                |row| {
+                    Ok(BackfillState {
+                        head: row.get(0)?,
+                        branch: row.get(1)?,
+                        cursor: row.get(2)?,
+                        commits: row.get(3)?,
+                        completed: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(state)
+    }
+
+    pub fn get_total_aggregates(
+        &self,
+        request: TotalAggregatesRequest,
+    ) -> Result<TotalAggregatesReply> {
+        let TotalAggregatesRequest {
+            table,
+            columns,
+            since: from,
+            until: to,
+            group_by: group_by_columns,
+            filter,
+            ..
+        } = request;
+        validate_identifier(&table)?;
+        if columns.is_empty() {
+            return Err(Error::EmptyColumns);
+        }
+
+        let group_by = create_group_by(group_by_columns)?;
+        // Parameters in the order of their placeholders: aggregates, time
+        // range, filter.
+        let mut params = Vec::new();
+        let projection = create_projection(&columns, group_by.clone(), &mut params)?;
+        params.extend(vec![types::Value::Integer(from), types::Value::Integer(to)]);
+        let filter = filter
+            .map(|filter| self.create_filter(&table, &filter, &mut params))
+            .transpose()?;
+
+        let values_range = 0..columns.len();
+        let groups_range = values_range.end..values_range.end + group_by.len();
+
+        let is_grouped = !group_by.is_empty();
+        let sql = create_aggregate_query_sql(projection, table, filter, group_by, None);
+
+        log_event(format!("sql: {}", sql));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = if is_grouped {
+            stmt.query_map(&params, |row| {
+                Ok(total_aggregates_reply::Row {
+                    values: values_range
+                        .clone()
+                        .map(|i| row.get(i))
+                        .collect::<rusqlite::Result<_>>()?,
+                    groups: groups_range
+                        .clone()
+                        .map(|i| row.get(i))
+                        .collect::<rusqlite::Result<_>>()?,
+                })
+            })?
+            .map(|row| row.map_err(|err| err.into()))
+            .collect::<Result<_>>()?
+        } else {
+            // Without a GROUP BY clause SQLite always returns exactly 1 row.
+            // If not rows match the WHERE clause, some aggregate functions
+            // like avg() or max() return NULL.
+            stmt.query_row(&params, |row| {
+                let values: Vec<Option<f64>> = values_range
+                    .clone()
+                    .map(|i| row.get(i))
+                    .collect::<rusqlite::Result<_>>()?;
+                let rows = if values.iter().any(Option::is_none) {
+                    Vec::new()
+                } else {
+                    vec![total_aggregates_reply::Row {
+                        values: values.into_iter().map(Option::unwrap).collect(),
+                        groups: Vec::new(),
+                    }]
+                };
+                Ok(rows)
+            })?
+        };
+
+        Ok(TotalAggregatesReply { rows })
+    }
+
+    pub fn get_interval_aggregates(
+        &self,
+        request: IntervalAggregatesRequest,
+    ) -> Result<IntervalAggregatesReply> {
+        let interval_type = request.interval();
+        let IntervalAggregatesRequest {
+            table,
+            columns,
+            since: from,
+            until: to,
+            group_by: group_by_columns,
+            filter,
+            timezone,
+            ..
+        } = request;
+        validate_identifier(&table)?;
+        if columns.is_empty() {
+            return Err(Error::EmptyColumns);
+        }
+
+        let mut group_by = create_group_by(group_by_columns.clone())?;
+        let mut filter_params = Vec::new();
+        let filter_sql = filter
+            .as_ref()
+            .map(|filter| self.create_filter(&table, filter, &mut filter_params))
+            .transpose()?;
+        let mut aggregate_params = Vec::new();
+        let mut aggregates = create_aggregates(&columns, &mut aggregate_params)?;
+
+        let values_range = 0..columns.len();
+        let groups_range = values_range.end..values_range.end + group_by.len();
+        let timestamp_index = groups_range.end;
+
+        let time_range = to - from;
+        if time_range <= 0 {
+            return Err(Error::InvalidTimeRange);
+        }
+        let (interval, calendar_intervals) = match interval_type {
+            IntervalType::Sparse | IntervalType::Detailed => {
+                let interval = match interval_type {
+                    IntervalType::Sparse => time_range / 120,
+                    _ => time_range / 720,
+                };
+                (
+                    format!("timestamp / {} * {} AS interval", interval, interval),
+                    None,
+                )
+            }
+            IntervalType::Hour | IntervalType::Day | IntervalType::Week | IntervalType::Month => {
+                let tz = intervals::parse_timezone(&timezone)?;
+                let starts = intervals::calendar_intervals(interval_type, tz, from, to)?;
+                functions::register_interval_start(&self.conn, starts.clone())?;
+                (
+                    "interval_start(timestamp) AS interval".to_owned(),
+                    Some(starts),
+                )
+            }
+        };
+
+        // Calendar intervals aligned to hours or days are aggregated from
+        // rollups where possible, and from rows only at the edges of the time
+        // range.
+        let has_rollups = schema::version(&self.conn)? >= schema::ROLLUPS_VERSION;
+        let plan = calendar_intervals
+            .as_ref()
+            .filter(|_| has_rollups)
+            .and_then(|starts| {
+                rollups::plan(
+                    &table,
+                    &columns,
+                    &group_by_columns,
+                    filter.as_ref(),
+                    starts,
+                    from,
+                    to,
+                )
+            });
+        let mut params = Vec::new();
+        let source = match plan {
+            Some(plan) => {
+                aggregates = plan.aggregates;
+                params.extend(plan.aggregate_params);
+                params.extend(plan.params);
+                plan.source
+            }
+            None => {
+                params.extend(aggregate_params);
+                table
+            }
+        };
+        params.extend(vec![types::Value::Integer(from), types::Value::Integer(to)]);
+        params.extend(filter_params);
+
+        let mut projection = create_projection_with(aggregates, group_by.clone());
+        projection.push(interval);
+        group_by.push("interval".into());
+
+        let sql =
+            create_aggregate_query_sql(projection, source, filter_sql, group_by, Some("interval"));
+
+        log_event(format!("sql: {}", sql));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(&params, |row| {
+                Ok(interval_aggregates_reply::Row {
+                    values: values_range
+                        .clone()
+                        .map(|i| row.get(i))
+                        .collect::<rusqlite::Result<_>>()?,
+                    groups: groups_range
+                        .clone()
+                        .map(|i| row.get(i))
+                        .collect::<rusqlite::Result<_>>()?,
+                    timestamp: row.get(timestamp_index)?,
+                })
+            })?
+            .map(|row| row.map_err(|err| err.into()))
+            .collect::<Result<_>>()?;
+        let rows = match calendar_intervals {
+            Some(starts) => fill_empty_intervals(rows, starts),
+            None => rows,
+        };
+
+        Ok(IntervalAggregatesReply { rows })
+    }
+
+    pub fn get_flaky_builds(&self, request: FlakyBuildsRequest) -> Result<FlakyBuildsReply> {
+        let FlakyBuildsRequest {
+            since: from,
+            until: to,
+            limit,
+            ..
+        } = request;
+        if to <= from {
+            return Err(Error::InvalidTimeRange);
+        }
+        // A negative LIMIT means no limit in SQLite.
+        let limit = if limit == 0 { -1 } else { i64::from(limit) };
+
+        // A build is flaky on a commit if it failed and a later run of the
+        // same build on the same commit succeeded.
+        let mut stmt = self.conn.prepare(
+            "WITH runs AS (
+                SELECT
+                    \"commit\",
+                    name,
+                    source,
+                    min(CASE WHEN failed THEN timestamp END) AS first_failure,
+                    max(CASE WHEN successful THEN timestamp END) AS last_success
+                FROM builds
+                WHERE timestamp >= ? AND timestamp <= ?
+                GROUP BY \"commit\", name, source
+            )
+            SELECT
+                name,
+                source,
+                count(*) AS commits,
+                group_concat(CASE WHEN first_failure < last_success THEN \"commit\" END) AS flaky
+            FROM runs
+            GROUP BY name, source
+            HAVING flaky IS NOT NULL
+            ORDER BY
+                total(first_failure < last_success) / count(*) DESC,
+                commits DESC,
+                name
+            LIMIT ?",
+        )?;
+        let builds = stmt
+            .query_map(params![from, to, limit], |row| {
+                let commits: u32 = row.get(2)?;
+                let flaky_comma_separated: String = row.get(3)?;
+                let flaky_commits: Vec<String> =
+                    flaky_comma_separated.split(',').map(String::from).collect();
+                Ok(flaky_builds_reply::FlakyBuild {
+                    name: row.get(0)?,
+                    source: row.get(1)?,
+                    commits,
+                    flakiness: flaky_commits.len() as f64 / f64::from(commits),
+                    flaky_commits,
+                })
+            })?
+            .map(|row| row.map_err(|err| err.into()))
+            .collect::<Result<_>>()?;
+        Ok(FlakyBuildsReply { builds })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Tz;
+
+    fn db() -> DB {
+        let mut conn = Connection::open_in_memory().unwrap();
+        functions::register(&conn).unwrap();
+        schema::up(&mut conn).unwrap();
+        for (timestamp, duration_ms) in &[(1000, 10), (2000, 20), (3000, 30), (4000, 40)] {
+            conn.execute(
+                "INSERT INTO builds(\"commit\", name, source, timestamp, successful, failed, duration_ms)
+                VALUES ('c1', 'ci', 1, ?, 1, 0, ?)",
+                params![timestamp, duration_ms],
+            )
+            .unwrap();
+        }
+        DB { conn }
+    }
+
+    fn column(name: &str, agg_func: AggregateFunction, percentile: f64) -> Column {
+        Column {
+            name: name.into(),
+            agg_func: agg_func as i32,
+            percentile,
+        }
+    }
+
+    fn total(db: &DB, columns: Vec<Column>, filter: Option<Filter>) -> Result<Vec<f64>> {
+        let reply = db.get_total_aggregates(TotalAggregatesRequest {
+            table: "builds".into(),
+            columns,
+            since: 0,
+            until: 10000,
+            filter,
+            ..Default::default()
+        })?;
+        Ok(reply.rows.into_iter().flat_map(|row| row.values).collect())
+    }
+
+    #[test]
+    fn binds_percentile() {
+        let db = db();
+        let mut params = Vec::new();
+        let sql = create_aggregate(
+            &column("duration_ms", AggregateFunction::Percentile, 50.0),
+            &mut params,
+        )
+        .unwrap();
+        assert_eq!(sql, "percentile(\"duration_ms\", ?)");
+        assert_eq!(params, vec![types::Value::Real(50.0)]);
+
+        let values = total(
+            &db,
+            vec![
+                column("duration_ms", AggregateFunction::Percentile, 50.0),
+                column("duration_ms", AggregateFunction::Max, 0.0),
+            ],
+            Some(Filter {
+                expr: Some(filter::Expr::Comparison(filter::Comparison {
+                    column: "timestamp".into(),
+                    op: ComparisonOperator::Ge as i32,
+                    value: Some(Value {
+                        kind: Some(value::Kind::Integer(2000)),
+                    }),
+                })),
+            }),
+        )
+        .unwrap();
+        assert_eq!(values, vec![30.0, 40.0]);
+    }
+
+    #[test]
+    fn rejects_empty_identifier() {
+        let db = db();
+        match total(&db, vec![column("", AggregateFunction::Count, 0.0)], None) {
+            Err(Error::InvalidIdentifier(name)) => assert_eq!(name, ""),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    fn comparison(column: &str) -> Filter {
+        Filter {
+            expr: Some(filter::Expr::Comparison(filter::Comparison {
+                column: column.into(),
+                op: ComparisonOperator::Eq as i32,
+                value: Some(Value {
+                    kind: Some(value::Kind::Text("ci".into())),
+                }),
+            })),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_filter_column() {
+        let db = db();
+        let count = || vec![column("name", AggregateFunction::Count, 0.0)];
+        assert_eq!(
+            total(&db, count(), Some(comparison("name"))).unwrap(),
+            vec![4.0]
+        );
+        match total(&db, count(), Some(comparison("nope"))) {
+            Err(Error::InvalidFilter(message)) => assert_eq!(message, "unknown column nope"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_deeply_nested_filter() {
+        let db = db();
+        let nested = |depth| {
+            (0..depth).fold(comparison("name"), |filter, _| Filter {
+                expr: Some(filter::Expr::And(filter::FilterList {
+                    filters: vec![filter],
+                })),
+            })
+        };
+        let count = || vec![column("name", AggregateFunction::Count, 0.0)];
+        assert!(total(&db, count(), Some(nested(MAX_FILTER_DEPTH))).is_ok());
+        match total(&db, count(), Some(nested(MAX_FILTER_DEPTH + 1))) {
+            Err(Error::InvalidFilter(message)) => assert_eq!(message, "too deeply nested"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rollups_match_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        functions::register(&conn).unwrap();
+        schema::up(&mut conn).unwrap();
+        let mut dirty = rollups::Dirty::default();
+        for i in 0..1000i64 {
+            // Every 17 minutes for almost 12 days.
+            let timestamp = i * 17 * 60 * 1000 + 12345;
+            conn.execute(
+                "INSERT INTO builds(\"commit\", name, source, timestamp, successful, failed, duration_ms)
+                VALUES (?, ?, 1, ?, ?, ?, ?)",
+                params![
+                    format!("c{}", i),
+                    if i % 2 == 0 { "ci" } else { "lint" },
+                    timestamp,
+                    i % 3 == 0,
+                    i % 3 != 0,
+                    i * i % 7919 + i % 5 * 100_000
+                ],
+            )
+            .unwrap();
+            dirty.mark("builds", timestamp);
+        }
+        rollups::refresh(&conn, &dirty).unwrap();
+        let db = DB { conn };
+
+        let columns = vec![
+            column("commit", AggregateFunction::Count, 0.0),
+            column("duration_ms", AggregateFunction::Sum, 0.0),
+            column("duration_ms", AggregateFunction::Min, 0.0),
+            column("duration_ms", AggregateFunction::Max, 0.0),
+            column("duration_ms", AggregateFunction::Avg, 0.0),
+            column("successful", AggregateFunction::Sum, 0.0),
+            column("duration_ms", AggregateFunction::Percentile, 50.0),
+            column("duration_ms", AggregateFunction::Percentile, 90.0),
+            column("duration_ms", AggregateFunction::Percentile, 99.0),
+        ];
+        let exact = 6;
+        // Days at the edges are partially read from rows.
+        let (since, until) = (
+            rollups::DAY + 5 * rollups::HOUR + 123,
+            9 * rollups::DAY + 7 * rollups::HOUR,
+        );
+        let group_by = vec!["name".to_owned()];
+        let starts =
+            intervals::calendar_intervals(IntervalType::Day, Tz::UTC, since, until).unwrap();
+        assert!(
+            rollups::plan("builds", &columns, &group_by, None, &starts, since, until).is_some()
+        );
+
+        let rows = |filter| {
+            let mut rows = db
+                .get_interval_aggregates(IntervalAggregatesRequest {
+                    table: "builds".into(),
+                    columns: columns.clone(),
+                    since,
+                    until,
+                    group_by: group_by.clone(),
+                    interval: IntervalType::Day as i32,
+                    filter,
+                    ..Default::default()
+                })
+                .unwrap()
+                .rows;
+            rows.sort_by(|a, b| (a.timestamp, &a.groups).cmp(&(b.timestamp, &b.groups)));
+            rows
+        };
+        let from_rollups = rows(None);
+        // Filters of other columns than dimensions can't use rollups.
+        let from_rows = rows(Some(Filter {
+            expr: Some(filter::Expr::Comparison(filter::Comparison {
+                column: "timestamp".into(),
+                op: ComparisonOperator::Ge as i32,
+                value: Some(Value {
+                    kind: Some(value::Kind::Integer(0)),
+                }),
+            })),
+        }));
+
+        assert_eq!(from_rollups.len(), 18);
+        assert_eq!(from_rollups.len(), from_rows.len());
+        for (rollup, row) in from_rollups.iter().zip(&from_rows) {
+            assert_eq!(
+                (rollup.timestamp, &rollup.groups),
+                (row.timestamp, &row.groups)
+            );
+            assert_eq!(rollup.values[..exact], row.values[..exact]);
+            for (estimate, actual) in rollup.values[exact..].iter().zip(&row.values[exact..]) {
+                assert!(
+                    (estimate - actual).abs() <= actual * 0.01,
+                    "{} estimated as {}",
+                    actual,
+                    estimate
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use super::{system_path, Result};
+use crate::proto::{RegisteredRepository, RepositoryStatus};
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS repositories (
+        repository_id   TEXT PRIMARY KEY,
+        installation_id INTEGER NOT NULL,
+        full_name       TEXT NOT NULL,
+        status          INTEGER NOT NULL,
+        updated_at      INTEGER NOT NULL
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS repositories_installation_id ON repositories(installation_id);";
+
+/// Lifecycle of all repositories, shared by all repository databases.
+/// Repositories that were never registered are treated as active.
+pub struct Registry {
+    conn: Connection,
+}
+
+impl Registry {
+    pub fn open(directory: &str) -> Result<Registry> {
+        let path = system_path(directory, "registry")?;
+        let conn = Connection::open(path)?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Registry { conn })
+    }
+
+    pub fn upsert_repositories(&mut self, repositories: &[RegisteredRepository]) -> Result<()> {
+        let trx = self.conn.transaction()?;
+        {
+            let mut stmt = trx.prepare(
+                "INSERT INTO repositories(repository_id, installation_id, full_name, status, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT(repository_id) DO UPDATE SET
+                    installation_id = excluded.installation_id,
+                    full_name = CASE WHEN excluded.full_name = '' THEN full_name ELSE excluded.full_name END,
+                    status = excluded.status,
+                    updated_at = excluded.updated_at",
+            )?;
+            for repository in repositories {
+                stmt.execute(params![
+                    repository.repository_id,
+                    repository.installation_id,
+                    repository.full_name,
+                    repository.status,
+                    repository.updated_at
+                ])?;
+            }
+        }
+        trx.commit()?;
+        Ok(())
+    }
+
+    /// Changes the status of all repositories of the installation and returns
+    /// the IDs of the repositories that changed.
+    pub fn update_installation(
+        &mut self,
+        installation_id: i64,
+        status: RepositoryStatus,
+        timestamp: i64,
+    ) -> Result<Vec<String>> {
+        let from: &[RepositoryStatus] = match status {
+            RepositoryStatus::Active => &[RepositoryStatus::Suspended],
+            RepositoryStatus::Suspended => &[RepositoryStatus::Active],
+            RepositoryStatus::Removed => &[RepositoryStatus::Active, RepositoryStatus::Suspended],
+            RepositoryStatus::Purged => &[],
+        };
+        let trx = self.conn.transaction()?;
+        let mut repository_ids = Vec::new();
+        {
+            let mut select = trx.prepare(
+                "SELECT repository_id FROM repositories WHERE installation_id = ? AND status = ?",
+            )?;
+            let mut update = trx.prepare(
+                "UPDATE repositories SET status = ?, updated_at = ? WHERE repository_id = ?",
+            )?;
+            for from in from {
+                let ids = select
+                    .query_map(params![installation_id, *from as i32], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<String>>>()?;
+                for id in ids {
+                    update.execute(params![status as i32, timestamp, id])?;
+                    repository_ids.push(id);
+                }
+            }
+        }
+        trx.commit()?;
+        Ok(repository_ids)
+    }
+
+    pub fn get_repositories(&self) -> Result<Vec<RegisteredRepository>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT repository_id, installation_id, full_name, status, updated_at
+            FROM repositories
+            ORDER BY repository_id",
+        )?;
+        let repositories = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok(RegisteredRepository {
+                    repository_id: row.get(0)?,
+                    installation_id: row.get(1)?,
+                    full_name: row.get(2)?,
+                    status: row.get(3)?,
+                    updated_at: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(repositories)
+    }
+
+    pub fn get_status(&self, repository_id: &str) -> Result<Option<RepositoryStatus>> {
+        let status: Option<i32> = self
+            .conn
+            .query_row(
+                "SELECT status FROM repositories WHERE repository_id = ?",
+                params![repository_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(status.and_then(RepositoryStatus::from_i32))
+    }
+
+    pub fn is_active(&self, repository_id: &str) -> Result<bool> {
+        Ok(matches!(
+            self.get_status(repository_id)?,
+            None | Some(RepositoryStatus::Active)
+        ))
+    }
+
+    /// Returns the repositories removed before the given time whose data
+    /// wasn't purged yet.
+    pub fn get_removed_before(&self, timestamp: i64) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT repository_id FROM repositories WHERE status = ? AND updated_at < ?",
+        )?;
+        let repository_ids = stmt
+            .query_map(
+                params![RepositoryStatus::Removed as i32, timestamp],
+                |row| row.get(0),
+            )?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(repository_ids)
+    }
+
+    /// Sets the status of a repository, registering it if it's unknown.
+    pub fn set_status(
+        &self,
+        repository_id: &str,
+        status: RepositoryStatus,
+        timestamp: i64,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO repositories(repository_id, installation_id, full_name, status, updated_at)
+            VALUES (?, 0, '', ?, ?)
+            ON CONFLICT(repository_id) DO UPDATE SET
+                status = excluded.status,
+                updated_at = excluded.updated_at",
+            params![repository_id, status as i32, timestamp],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,394 @@
+//! Hourly and daily rollups of the builds, which answer interval aggregates
+//! without reading every row.
+//!
+//! Interval aggregates of calendar intervals aligned to hours or days are
+//! read from the rollups when grouped and filtered by build name and source
+//! only. Their percentiles are then estimates within 1% of the actual value,
+//! including the rows at the edges of the time range, and values of 0 and
+//! below are estimated as 0. Counts, sums, minimums, maximums and averages
+//! are always exact.
+
+use super::Result;
+use crate::proto::{filter, AggregateFunction, Column, Filter};
+use rusqlite::{params, types, Connection};
+use std::collections::BTreeSet;
+
+pub const HOUR: i64 = 60 * 60 * 1000;
+pub const DAY: i64 = 24 * HOUR;
+
+// Rollup periods, coarsest first. Daily rollups are aggregated from hourly
+// ones and aligned to UTC days.
+const PERIODS: &[i64] = &[DAY, HOUR];
+
+/// Table with rollups in `{name}_rollups`. Each rollup row aggregates the
+/// rows of one period per combination of dimensions, with the count of rows
+/// and the sum, min, max and sketch of each measure.
+struct Table {
+    name: &'static str,
+    dimensions: &'static [&'static str],
+    measures: &'static [&'static str],
+    // Counting these columns counts rows, as they can't be NULL.
+    other_columns: &'static [&'static str],
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "builds",
+        dimensions: &["name", "source"],
+        measures: &["duration_ms", "queue_ms", "successful", "failed"],
+        other_columns: &[
+            "commit",
+            "timestamp",
+            "branch",
+            "pull_request",
+            "workflow_name",
+            "event",
+            "attempt",
+            "queued_at",
+            "outcome",
+        ],
+    },
+    Table {
+        name: "commits",
+        dimensions: &["build_name", "build_source"],
+        measures: &["builds", "builds_successful", "builds_failed"],
+        other_columns: &["commit", "timestamp", "branch", "pull_request"],
+    },
+];
+
+fn find_table(name: &str) -> Option<&'static Table> {
+    TABLES.iter().find(|table| table.name == name)
+}
+
+fn quote(columns: &[&str]) -> Vec<String> {
+    columns
+        .iter()
+        .map(|column| format!("\"{}\"", column))
+        .collect()
+}
+
+/// Hours whose rollups are outdated, per table.
+#[derive(Default)]
+pub struct Dirty {
+    hours: BTreeSet<(&'static str, i64)>,
+}
+
+impl Dirty {
+    /// Marks the hour containing the timestamp as outdated.
+    pub fn mark(&mut self, table: &'static str, timestamp: i64) {
+        self.hours
+            .insert((table, timestamp.div_euclid(HOUR) * HOUR));
+    }
+
+    /// Marks all hours with rollups in the time range [since, until) as
+    /// outdated.
+    pub fn mark_range(
+        &mut self,
+        conn: &Connection,
+        table: &'static str,
+        since: i64,
+        until: i64,
+    ) -> Result<()> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT start FROM {}_rollups WHERE period = ? AND start >= ? AND start < ?",
+            table
+        ))?;
+        let hours = stmt
+            .query_map(params![HOUR, since.div_euclid(HOUR) * HOUR, until], |row| {
+                row.get(0)
+            })?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        self.hours
+            .extend(hours.into_iter().map(|hour| (table, hour)));
+        Ok(())
+    }
+}
+
+// Recomputes the rollups of one period. Hourly rollups are computed from the
+// rows of the table, daily rollups from the hourly rollups.
+fn refresh_rollup(conn: &Connection, table: &Table, period: i64, start: i64) -> Result<()> {
+    let dimensions = quote(table.dimensions);
+    let mut columns = vec!["period".to_owned(), "start".to_owned()];
+    columns.extend(dimensions.iter().cloned());
+    columns.push("row_count".into());
+    let mut aggregates = vec!["?1".to_owned(), "?2".to_owned()];
+    aggregates.extend(dimensions.iter().cloned());
+    let source = if period == HOUR {
+        aggregates.push("count(*)".into());
+        for measure in table.measures {
+            aggregates.push(format!("sum(\"{}\")", measure));
+            aggregates.push(format!("min(\"{}\")", measure));
+            aggregates.push(format!("max(\"{}\")", measure));
+            aggregates.push(format!("sketch(\"{}\")", measure));
+        }
+        format!(
+            "{} WHERE timestamp >= ?2 AND timestamp < ?2 + ?1",
+            table.name
+        )
+    } else {
+        aggregates.push("sum(row_count)".into());
+        for measure in table.measures {
+            aggregates.push(format!("sum(\"{}_sum\")", measure));
+            aggregates.push(format!("min(\"{}_min\")", measure));
+            aggregates.push(format!("max(\"{}_max\")", measure));
+            aggregates.push(format!("sketch_merge(\"{}_sketch\")", measure));
+        }
+        format!(
+            "{}_rollups WHERE period = {} AND start >= ?2 AND start < ?2 + ?1",
+            table.name, HOUR
+        )
+    };
+    for measure in table.measures {
+        for suffix in &["sum", "min", "max", "sketch"] {
+            columns.push(format!("\"{}_{}\"", measure, suffix));
+        }
+    }
+
+    conn.prepare_cached(&format!(
+        "DELETE FROM {}_rollups WHERE period = ?1 AND start = ?2",
+        table.name
+    ))?
+    .execute(params![period, start])?;
+    conn.prepare_cached(&format!(
+        "INSERT INTO {}_rollups({}) SELECT {} FROM {} GROUP BY {}",
+        table.name,
+        columns.join(", "),
+        aggregates.join(", "),
+        source,
+        dimensions.join(", ")
+    ))?
+    .execute(params![period, start])?;
+    Ok(())
+}
+
+/// Recomputes the hourly rollups of the outdated hours and the daily rollups
+/// of the days containing them.
+pub fn refresh(conn: &Connection, dirty: &Dirty) -> Result<()> {
+    let mut days = BTreeSet::new();
+    for (name, hour) in &dirty.hours {
+        let table = find_table(name).expect("rollups of unknown table");
+        refresh_rollup(conn, table, HOUR, *hour)?;
+        days.insert((*name, hour.div_euclid(DAY) * DAY));
+    }
+    for (name, day) in days {
+        let table = find_table(name).expect("rollups of unknown table");
+        refresh_rollup(conn, table, DAY, day)?;
+    }
+    Ok(())
+}
+
+/// Query of interval aggregates answered from rollups.
+pub struct Plan {
+    /// Subquery replacing the table, with the rollups of the covered range
+    /// and the rows of the remaining edges of the time range.
+    pub source: String,
+    /// Parameters of `source`.
+    pub params: Vec<types::Value>,
+    /// Aggregates of the requested columns over `source`.
+    pub aggregates: Vec<String>,
+    /// Parameters of `aggregates`.
+    pub aggregate_params: Vec<types::Value>,
+}
+
+// Field of the rollups, with the matching expression for a single row.
+struct Field {
+    name: String,
+    row: String,
+}
+
+impl Field {
+    fn row_count() -> Field {
+        Field {
+            name: "row_count".into(),
+            row: "1".into(),
+        }
+    }
+
+    fn measure(measure: &str, suffix: &str) -> Field {
+        let row = match suffix {
+            "sketch" => format!("sketch_value(\"{}\")", measure),
+            _ => format!("\"{}\"", measure),
+        };
+        Field {
+            name: format!("{}_{}", measure, suffix),
+            row,
+        }
+    }
+}
+
+fn add_filter_columns<'a>(filter: &'a Filter, columns: &mut Vec<&'a str>) -> Option<()> {
+    match filter.expr.as_ref()? {
+        filter::Expr::Comparison(comparison) => columns.push(&comparison.column),
+        filter::Expr::InList(in_list) => columns.push(&in_list.column),
+        filter::Expr::And(list) | filter::Expr::Or(list) => {
+            for filter in &list.filters {
+                add_filter_columns(filter, columns)?;
+            }
+        }
+    }
+    Some(())
+}
+
+// Period of the coarsest rollups that fit into the intervals, with the range
+// [from, until) covered by them.
+fn choose_period(starts: &[i64], from: i64, to: i64) -> Option<(i64, i64, i64)> {
+    PERIODS.iter().find_map(|&period| {
+        if starts.iter().any(|start| start.rem_euclid(period) != 0) {
+            return None;
+        }
+        let covered_from = from.saturating_add(period - 1).div_euclid(period) * period;
+        let covered_until = to.saturating_add(1).div_euclid(period) * period;
+        if covered_from < covered_until {
+            Some((period, covered_from, covered_until))
+        } else {
+            None
+        }
+    })
+}
+
+/// Plans reading interval aggregates of a table from its rollups. Returns
+/// `None` if the rollups can't answer the query: only aggregates of measures,
+/// counts, and grouping and filtering by dimensions are supported, and the
+/// interval starts (calendar intervals only) have to be aligned to a rollup
+/// period. Columns have to be validated before. Percentiles are estimated
+/// from sketches, also for the rows at the edges of the time range.
+pub fn plan(
+    table: &str,
+    columns: &[Column],
+    group_by: &[String],
+    filter: Option<&Filter>,
+    starts: &[i64],
+    from: i64,
+    to: i64,
+) -> Option<Plan> {
+    let table = find_table(table)?;
+    let is_dimension = |column: &str| table.dimensions.contains(&column);
+    if !group_by.iter().all(|column| is_dimension(column)) {
+        return None;
+    }
+    if let Some(filter) = filter {
+        let mut filter_columns = Vec::new();
+        add_filter_columns(filter, &mut filter_columns)?;
+        if !filter_columns.into_iter().all(is_dimension) {
+            return None;
+        }
+    }
+
+    let mut fields: Vec<Field> = vec![Field::row_count()];
+    let mut field = |field: Field| {
+        let name = format!("\"{}\"", field.name);
+        if fields.iter().all(|f| f.name != field.name) {
+            fields.push(field);
+        }
+        name
+    };
+    let mut aggregates = Vec::new();
+    let mut aggregate_params = Vec::new();
+    for column in columns {
+        let name = column.name.as_str();
+        let aggregate = match column.agg_func() {
+            AggregateFunction::Count => {
+                if !is_dimension(name)
+                    && !table.measures.contains(&name)
+                    && !table.other_columns.contains(&name)
+                {
+                    return None;
+                }
+                "sum(\"row_count\")".to_owned()
+            }
+            _ if !table.measures.contains(&name) => return None,
+            AggregateFunction::Sum => format!("sum({})", field(Field::measure(name, "sum"))),
+            AggregateFunction::Min => format!("min({})", field(Field::measure(name, "min"))),
+            AggregateFunction::Max => format!("max({})", field(Field::measure(name, "max"))),
+            AggregateFunction::Avg => format!(
+                "sum({}) * 1.0 / sum(\"row_count\")",
+                field(Field::measure(name, "sum"))
+            ),
+            AggregateFunction::Percentile => {
+                aggregate_params.push(types::Value::Real(column.percentile));
+                format!(
+                    "sketch_percentile({}, ?)",
+                    field(Field::measure(name, "sketch"))
+                )
+            }
+        };
+        aggregates.push(aggregate);
+    }
+
+    let (period, covered_from, covered_until) = choose_period(starts, from, to)?;
+
+    let dimensions = quote(table.dimensions);
+    let mut rollup_fields = vec!["start AS timestamp".to_owned()];
+    rollup_fields.extend(dimensions.iter().cloned());
+    rollup_fields.extend(fields.iter().map(|field| format!("\"{}\"", field.name)));
+    let mut row_fields = vec!["timestamp".to_owned()];
+    row_fields.extend(dimensions);
+    row_fields.extend(fields.iter().map(|field| field.row.clone()));
+    let rows = format!(
+        "SELECT {} FROM {} WHERE timestamp >= ? AND timestamp < ?",
+        row_fields.join(", "),
+        table.name
+    );
+    let source = format!(
+        "(SELECT {} FROM {}_rollups WHERE period = ? AND start >= ? AND start < ? UNION ALL {} UNION ALL {})",
+        rollup_fields.join(", "),
+        table.name,
+        rows,
+        rows
+    );
+    let params = vec![
+        period,
+        covered_from,
+        covered_until,
+        from,
+        covered_from,
+        covered_until,
+        to.saturating_add(1),
+    ]
+    .into_iter()
+    .map(types::Value::Integer)
+    .collect();
+
+    Some(Plan {
+        source,
+        params,
+        aggregates,
+        aggregate_params,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chooses_coarsest_aligned_period() {
+        let days = [0, DAY, 2 * DAY];
+        assert_eq!(
+            choose_period(&days, 0, 3 * DAY - 1),
+            Some((DAY, 0, 3 * DAY))
+        );
+        // Only whole days within the time range are covered.
+        assert_eq!(
+            choose_period(&days, HOUR, 3 * DAY - 2),
+            Some((DAY, DAY, 2 * DAY))
+        );
+        // Time ranges shorter than a day fall back to hours.
+        assert_eq!(
+            choose_period(&days, HOUR, DAY + HOUR),
+            Some((HOUR, HOUR, DAY + HOUR))
+        );
+    }
+
+    #[test]
+    fn requires_aligned_intervals() {
+        let hours = [HOUR, 2 * HOUR];
+        assert_eq!(
+            choose_period(&hours, HOUR, 3 * HOUR - 1),
+            Some((HOUR, HOUR, 3 * HOUR))
+        );
+        assert_eq!(choose_period(&[HOUR + 1], HOUR, 3 * HOUR), None);
+        // Nothing to cover within a single hour.
+        assert_eq!(choose_period(&hours, HOUR + 1, 2 * HOUR - 1), None);
+    }
+}
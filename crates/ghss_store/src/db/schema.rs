@@ -1,36 +1,308 @@
-use rusqlite::{Connection, Result};
-
-pub fn up(conn: &Connection) -> Result<()> {
-    conn.execute_batch(
-        "BEGIN;
-        CREATE TABLE IF NOT EXISTS builds (
-            \"commit\"  TEXT NOT NULL,
-            name        TEXT NOT NULL,
-            source      INTEGER NOT NULL,
-            timestamp   INTEGER NOT NULL,
-            successful  INTEGER NOT NULL,
-            failed      INTEGER NOT NULL,
-            duration_ms INTEGER NOT NULL,
-            PRIMARY KEY(\"commit\", name, source, timestamp)
-        ) WITHOUT ROWID;
-        CREATE TABLE IF NOT EXISTS commits (
-            \"commit\"        TEXT NOT NULL,
-            build_name        TEXT NOT NULL,
-            build_source      INTEGER NOT NULL,
-            builds            INTEGER NOT NULL,
-            builds_successful INTEGER NOT NULL,
-            builds_failed     INTEGER NOT NULL,
-            timestamp         INTEGER NOT NULL,
-            PRIMARY KEY(\"commit\", build_name, build_source)
-        ) WITHOUT ROWID;
-        CREATE TABLE IF NOT EXISTS imports (
-            timestamp INTEGER PRIMARY KEY
-        ) WITHOUT ROWID;
-        CREATE TABLE IF NOT EXISTS hooks (
-            timestamp  INTEGER PRIMARY KEY,
-            type       INTEGER NOT NULL,
-            \"commit\" TEXT NOT NULL
-        ) WITHOUT ROWID;
-        COMMIT;",
-    )
-}
+use super::{Error, Result};
+use rusqlite::{Connection, TransactionBehavior, NO_PARAMS};
+
+// Migrations are applied in order. The schema version stored in the database
+// (`PRAGMA user_version`) is the number of migrations applied so far. Never
+// change or reorder existing migrations; only append new ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE IF NOT EXISTS builds (
+        \"commit\"  TEXT NOT NULL,
+        name        TEXT NOT NULL,
+        source      INTEGER NOT NULL,
+        timestamp   INTEGER NOT NULL,
+        successful  INTEGER NOT NULL,
+        failed      INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        PRIMARY KEY(\"commit\", name, source, timestamp)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS commits (
+        \"commit\"        TEXT NOT NULL,
+        build_name        TEXT NOT NULL,
+        build_source      INTEGER NOT NULL,
+        builds            INTEGER NOT NULL,
+        builds_successful INTEGER NOT NULL,
+        builds_failed     INTEGER NOT NULL,
+        timestamp         INTEGER NOT NULL,
+        PRIMARY KEY(\"commit\", build_name, build_source)
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS imports (
+        timestamp INTEGER PRIMARY KEY
+    ) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS hooks (
+        timestamp  INTEGER PRIMARY KEY,
+        type       INTEGER NOT NULL,
+        \"commit\" TEXT NOT NULL
+    ) WITHOUT ROWID;",
+    // 2: branch and pull request of builds
+    "ALTER TABLE builds ADD COLUMN branch TEXT NOT NULL DEFAULT '';
+    ALTER TABLE builds ADD COLUMN pull_request INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE commits ADD COLUMN branch TEXT NOT NULL DEFAULT '';
+    ALTER TABLE commits ADD COLUMN pull_request INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE hooks ADD COLUMN branch TEXT NOT NULL DEFAULT '';
+    ALTER TABLE hooks ADD COLUMN pull_request INTEGER NOT NULL DEFAULT 0;",
+    // 3: backfill checkpoint
+    "CREATE TABLE backfill (
+        id        INTEGER PRIMARY KEY CHECK (id = 0),
+        head      TEXT NOT NULL,
+        branch    TEXT NOT NULL,
+        cursor    TEXT NOT NULL,
+        commits   INTEGER NOT NULL,
+        completed INTEGER NOT NULL
+    );",
+    // 4: GitHub Actions workflow runs and jobs
+    "ALTER TABLE builds ADD COLUMN workflow_name TEXT NOT NULL DEFAULT '';
+    ALTER TABLE builds ADD COLUMN event TEXT NOT NULL DEFAULT '';
+    ALTER TABLE builds ADD COLUMN attempt INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE builds ADD COLUMN queue_ms INTEGER NOT NULL DEFAULT 0;",
+    // 5: queue time
+    "ALTER TABLE builds ADD COLUMN queued_at INTEGER NOT NULL DEFAULT 0;",
+    // 6: build outcome, derived from successful and failed for existing rows
+    // (UNKNOWN = 0, SUCCESS = 2, FAILURE = 3)
+    "ALTER TABLE builds ADD COLUMN outcome INTEGER NOT NULL DEFAULT 0;
+    UPDATE builds SET outcome = CASE WHEN successful THEN 2 WHEN failed THEN 3 ELSE 0 END;",
+    // 7: hooks keyed by row instead of timestamp, deduplicated by delivery
+    "CREATE TABLE hooks_new (
+        id           INTEGER PRIMARY KEY,
+        delivery     TEXT NOT NULL DEFAULT '',
+        timestamp    INTEGER NOT NULL,
+        type         INTEGER NOT NULL,
+        \"commit\"   TEXT NOT NULL,
+        branch       TEXT NOT NULL DEFAULT '',
+        pull_request INTEGER NOT NULL DEFAULT 0
+    );
+    INSERT INTO hooks_new(timestamp, type, \"commit\", branch, pull_request)
+        SELECT timestamp, type, \"commit\", branch, pull_request FROM hooks;
+    DROP TABLE hooks;
+    ALTER TABLE hooks_new RENAME TO hooks;
+    CREATE INDEX hooks_timestamp ON hooks(timestamp);
+    CREATE UNIQUE INDEX hooks_delivery ON hooks(delivery) WHERE delivery != '';",
+    // 8: hourly and daily rollups (period 3600000 and 86400000) per build,
+    // built from existing rows. Requires the sketch functions.
+    "CREATE INDEX builds_timestamp ON builds(timestamp);
+    CREATE INDEX commits_timestamp ON commits(timestamp);
+    CREATE TABLE builds_rollups (
+        period             INTEGER NOT NULL,
+        start              INTEGER NOT NULL,
+        name               TEXT NOT NULL,
+        source             INTEGER NOT NULL,
+        row_count          INTEGER NOT NULL,
+        duration_ms_sum    INTEGER NOT NULL,
+        duration_ms_min    INTEGER NOT NULL,
+        duration_ms_max    INTEGER NOT NULL,
+        duration_ms_sketch BLOB NOT NULL,
+        queue_ms_sum       INTEGER NOT NULL,
+        queue_ms_min       INTEGER NOT NULL,
+        queue_ms_max       INTEGER NOT NULL,
+        queue_ms_sketch    BLOB NOT NULL,
+        successful_sum     INTEGER NOT NULL,
+        successful_min     INTEGER NOT NULL,
+        successful_max     INTEGER NOT NULL,
+        successful_sketch  BLOB NOT NULL,
+        failed_sum         INTEGER NOT NULL,
+        failed_min         INTEGER NOT NULL,
+        failed_max         INTEGER NOT NULL,
+        failed_sketch      BLOB NOT NULL,
+        PRIMARY KEY(period, start, name, source)
+    ) WITHOUT ROWID;
+    CREATE TABLE commits_rollups (
+        period                   INTEGER NOT NULL,
+        start                    INTEGER NOT NULL,
+        build_name               TEXT NOT NULL,
+        build_source             INTEGER NOT NULL,
+        row_count                INTEGER NOT NULL,
+        builds_sum               INTEGER NOT NULL,
+        builds_min               INTEGER NOT NULL,
+        builds_max               INTEGER NOT NULL,
+        builds_sketch            BLOB NOT NULL,
+        builds_successful_sum    INTEGER NOT NULL,
+        builds_successful_min    INTEGER NOT NULL,
+        builds_successful_max    INTEGER NOT NULL,
+        builds_successful_sketch BLOB NOT NULL,
+        builds_failed_sum        INTEGER NOT NULL,
+        builds_failed_min        INTEGER NOT NULL,
+        builds_failed_max        INTEGER NOT NULL,
+        builds_failed_sketch     BLOB NOT NULL,
+        PRIMARY KEY(period, start, build_name, build_source)
+    ) WITHOUT ROWID;
+    INSERT INTO builds_rollups
+        SELECT 3600000, timestamp / 3600000 * 3600000 AS hour, name, source, count(*),
+            sum(duration_ms), min(duration_ms), max(duration_ms), sketch(duration_ms),
+            sum(queue_ms), min(queue_ms), max(queue_ms), sketch(queue_ms),
+            sum(successful), min(successful), max(successful), sketch(successful),
+            sum(failed), min(failed), max(failed), sketch(failed)
+        FROM builds
+        GROUP BY hour, name, source;
+    INSERT INTO builds_rollups
+        SELECT 86400000, start / 86400000 * 86400000 AS day, name, source, sum(row_count),
+            sum(duration_ms_sum), min(duration_ms_min), max(duration_ms_max), sketch_merge(duration_ms_sketch),
+            sum(queue_ms_sum), min(queue_ms_min), max(queue_ms_max), sketch_merge(queue_ms_sketch),
+            sum(successful_sum), min(successful_min), max(successful_max), sketch_merge(successful_sketch),
+            sum(failed_sum), min(failed_min), max(failed_max), sketch_merge(failed_sketch)
+        FROM builds_rollups
+        WHERE period = 3600000
+        GROUP BY day, name, source;
+    INSERT INTO commits_rollups
+        SELECT 3600000, timestamp / 3600000 * 3600000 AS hour, build_name, build_source, count(*),
+            sum(builds), min(builds), max(builds), sketch(builds),
+            sum(builds_successful), min(builds_successful), max(builds_successful), sketch(builds_successful),
+            sum(builds_failed), min(builds_failed), max(builds_failed), sketch(builds_failed)
+        FROM commits
+        GROUP BY hour, build_name, build_source;
+    INSERT INTO commits_rollups
+        SELECT 86400000, start / 86400000 * 86400000 AS day, build_name, build_source, sum(row_count),
+            sum(builds_sum), min(builds_min), max(builds_max), sketch_merge(builds_sketch),
+            sum(builds_successful_sum), min(builds_successful_min), max(builds_successful_max), sketch_merge(builds_successful_sketch),
+            sum(builds_failed_sum), min(builds_failed_min), max(builds_failed_max), sketch_merge(builds_failed_sketch)
+        FROM commits_rollups
+        WHERE period = 3600000
+        GROUP BY day, build_name, build_source;",
+];
+
+pub const VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// First version with rollup tables. Databases are only migrated by writers,
+/// so readers may still see older versions.
+pub const ROLLUPS_VERSION: u32 = 8;
+
+pub fn version(conn: &Connection) -> Result<u32> {
+    let version: u32 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+    if version > VERSION {
+        return Err(Error::UnsupportedSchemaVersion(version));
+    }
+
+    Ok(version)
+}
+
+pub fn up(conn: &mut Connection) -> Result<()> {
+    if version(conn)? == VERSION {
+        return Ok(());
+    }
+
+    // Another connection may have migrated the database in the meantime, so
+    // read the version again after acquiring the write lock.
+    let trx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version = version(&trx)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        trx.execute_batch(migration)?;
+    }
+    trx.pragma_update(None, "user_version", &VERSION)?;
+    trx.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::functions;
+
+    // Creates a database at the given historical version with one build, one
+    // commit and one hook, using only columns that exist in every version.
+    fn fixture(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        functions::register(&conn).unwrap();
+        for migration in &MIGRATIONS[..version as usize] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", &version).unwrap();
+        if version >= 1 {
+            conn.execute_batch(
+                "INSERT INTO builds(\"commit\", name, source, timestamp, successful, failed, duration_ms)
+                    VALUES ('c1', 'ci', 1, 7200000, 0, 1, 1000);
+                INSERT INTO commits(\"commit\", build_name, build_source, builds, builds_successful, builds_failed, timestamp)
+                    VALUES ('c1', 'ci', 1, 1, 0, 1, 7200000);
+                INSERT INTO hooks(timestamp, type, \"commit\") VALUES (7200000, 1, 'c1');",
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn upgrades_every_version() {
+        for from in 0..VERSION {
+            let mut conn = fixture(from);
+            up(&mut conn).unwrap();
+            assert_eq!(version(&conn).unwrap(), VERSION, "from {}", from);
+
+            let expected = if from >= 1 { 1 } else { 0 };
+            let builds: Vec<(String, i64, i64, String, i64)> = conn
+                .prepare("SELECT name, duration_ms, outcome, branch, queue_ms FROM builds")
+                .unwrap()
+                .query_map(NO_PARAMS, |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            assert_eq!(builds.len(), expected, "from {}", from);
+            if from >= 1 {
+                // Migration 6 derives the outcome from failed = 1 (FAILURE = 3).
+                // Newer fixtures keep the column default (UNKNOWN = 0).
+                let outcome = if from < 6 { 3 } else { 0 };
+                assert_eq!(builds[0], ("ci".into(), 1000, outcome, "".into(), 0));
+            }
+
+            let hooks: i64 = conn
+                .query_row(
+                    "SELECT count(*) FROM hooks WHERE delivery = ''",
+                    NO_PARAMS,
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(hooks, expected as i64, "from {}", from);
+
+            let rollups: Vec<(i64, i64, i64, i64)> = conn
+                .prepare(
+                    "SELECT period, start, row_count, duration_ms_sum FROM builds_rollups
+                    ORDER BY period",
+                )
+                .unwrap()
+                .query_map(NO_PARAMS, |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            if from >= 1 {
+                assert_eq!(
+                    rollups,
+                    vec![(3600000, 7200000, 1, 1000), (86400000, 0, 1, 1000)]
+                );
+            } else {
+                assert!(rollups.is_empty());
+            }
+            let commits_rollups: i64 = conn
+                .query_row("SELECT count(*) FROM commits_rollups", NO_PARAMS, |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            assert_eq!(commits_rollups, 2 * expected as i64, "from {}", from);
+        }
+    }
+
+    #[test]
+    fn up_is_idempotent() {
+        let mut conn = fixture(0);
+        up(&mut conn).unwrap();
+        up(&mut conn).unwrap();
+        assert_eq!(version(&conn).unwrap(), VERSION);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", &(VERSION + 1))
+            .unwrap();
+        match up(&mut conn) {
+            Err(Error::UnsupportedSchemaVersion(version)) => assert_eq!(version, VERSION + 1),
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+// Estimated percentiles are within 1% of the actual value.
+const RELATIVE_ACCURACY: f64 = 0.01;
+const GAMMA: f64 = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+
+/// Mergeable sketch of the distribution of non-negative values, used to
+/// estimate percentiles of rollups (DDSketch). Values are counted in buckets
+/// with exponentially growing bounds; bucket `i` covers
+/// `(GAMMA^(i-1), GAMMA^i]`. Zero and negative values share a bucket.
+#[derive(Default)]
+pub struct Sketch {
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl Sketch {
+    pub fn add(&mut self, value: f64) {
+        if value > 0.0 {
+            let index = (value.ln() / GAMMA.ln()).ceil() as i32;
+            *self.buckets.entry(index).or_default() += 1;
+        } else {
+            self.zeros += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Sketch) {
+        self.zeros += other.zeros;
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_default() += count;
+        }
+    }
+
+    fn count(&self) -> u64 {
+        self.zeros + self.buckets.values().sum::<u64>()
+    }
+
+    // Estimated value of the element with the given rank in ascending order.
+    fn value_at(&self, rank: u64) -> f64 {
+        if rank < self.zeros {
+            return 0.0;
+        }
+        let mut seen = self.zeros;
+        for (index, count) in &self.buckets {
+            seen += count;
+            if rank < seen {
+                return 2.0 * GAMMA.powi(*index) / (GAMMA + 1.0);
+            }
+        }
+        unreachable!("rank should be less than count")
+    }
+
+    /// Estimates the percentile in the range 0 to 100 the same way as the
+    /// `percentile` SQL function: by linear interpolation between the two
+    /// closest ranks.
+    pub fn percentile(&self, percentile: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = percentile / 100.0 * (count - 1) as f64;
+        let lower = self.value_at(rank.floor() as u64);
+        let upper = self.value_at(rank.ceil() as u64);
+        Some(lower + (upper - lower) * rank.fract())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.buckets.len() * 12);
+        bytes.extend_from_slice(&self.zeros.to_le_bytes());
+        for (index, count) in &self.buckets {
+            bytes.extend_from_slice(&index.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Sketch> {
+        if bytes.len() < 8 {
+            return None;
+        }
+        let zeros = u64::from_le_bytes(bytes[..8].try_into().ok()?);
+        let chunks = bytes[8..].chunks_exact(12);
+        if !chunks.remainder().is_empty() {
+            return None;
+        }
+        let buckets = chunks
+            .map(|bucket| {
+                Some((
+                    i32::from_le_bytes(bucket[..4].try_into().ok()?),
+                    u64::from_le_bytes(bucket[4..].try_into().ok()?),
+                ))
+            })
+            .collect::<Option<_>>()?;
+        Some(Sketch { zeros, buckets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(values: impl IntoIterator<Item = f64>) -> Sketch {
+        let mut sketch = Sketch::default();
+        for value in values {
+            sketch.add(value);
+        }
+        sketch
+    }
+
+    // Percentile of the sorted values, computed like the `percentile` SQL
+    // function.
+    fn exact(sorted: &[f64], percentile: f64) -> f64 {
+        let rank = percentile / 100.0 * (sorted.len() - 1) as f64;
+        let lower = sorted[rank.floor() as usize];
+        let upper = sorted[rank.ceil() as usize];
+        lower + (upper - lower) * rank.fract()
+    }
+
+    #[test]
+    fn estimates_within_relative_accuracy() {
+        let mut values: Vec<f64> = (1..=1000).map(|i| (i * i % 7919) as f64 + 1.0).collect();
+        let sketch = sketch(values.iter().cloned());
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for percentile in &[0.0, 1.0, 25.0, 50.0, 90.0, 99.0, 100.0] {
+            let actual = exact(&values, *percentile);
+            let estimate = sketch.percentile(*percentile).unwrap();
+            assert!(
+                (estimate - actual).abs() <= actual * RELATIVE_ACCURACY,
+                "p{}: {} estimated as {}",
+                percentile,
+                actual,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn estimates_non_positive_values_as_zero() {
+        let sketch = sketch(vec![-5.0, 0.0, 10.0]);
+        assert_eq!(sketch.percentile(0.0), Some(0.0));
+        assert_eq!(sketch.percentile(50.0), Some(0.0));
+        assert!((sketch.percentile(100.0).unwrap() - 10.0).abs() <= 10.0 * RELATIVE_ACCURACY);
+    }
+
+    #[test]
+    fn empty_sketch_has_no_percentile() {
+        assert_eq!(Sketch::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn merges_sketches() {
+        let mut merged = sketch(vec![0.0, 1.0, 2.0]);
+        merged.merge(&sketch(vec![2.0, 300.0]));
+        let all = sketch(vec![0.0, 1.0, 2.0, 2.0, 300.0]);
+        assert_eq!(merged.to_bytes(), all.to_bytes());
+    }
+
+    #[test]
+    fn roundtrips_bytes() {
+        let sketch = sketch(vec![0.0, 1.5, 1.5, 1e9]);
+        let bytes = sketch.to_bytes();
+        assert_eq!(Sketch::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+        assert!(Sketch::from_bytes(&bytes[..7]).is_none());
+        assert!(Sketch::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+    }
+}
@@ -1,99 +1,222 @@
-use super::schema;
-use super::Result;
-use crate::proto::{Build, Commit, Hook};
-use rusqlite::{params, Connection};
-
-pub struct DB {
-    conn: Connection,
-}
-
-impl DB {
-    pub fn open(directory: &str, repository_id: &str) -> Result<DB> {
-        let path = format!("{}/{}.db", directory, repository_id);
-        let conn = Connection::open(path)?;
-        schema::up(&conn)?;
-        Ok(DB { conn })
-    }
-
-    pub fn transaction(&mut self) -> Result<Transaction> {
-        Ok(Transaction {
-            transaction: self.conn.transaction()?,
-        })
-    }
-}
-
-pub struct Transaction<'conn> {
-    transaction: rusqlite::Transaction<'conn>,
-}
-
-impl Transaction<'_> {
-    pub fn upsert_builds(&self, builds: &[Build]) -> Result<()> {
-        let mut stmt = self.transaction.prepare(
-            "INSERT INTO builds(\"commit\", name, source, timestamp, successful, failed, duration_ms)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(\"commit\", name, source, timestamp) DO UPDATE SET
-                successful = excluded.successful,
-                failed = excluded.failed,
-                duration_ms = excluded.duration_ms",
-        )?;
-        for build in builds {
-            stmt.execute(params![
-                build.commit,
-                build.name,
-                build.source,
-                build.timestamp,
-                build.successful,
-                build.failed,
-                build.duration_ms
-            ])?;
-        }
-        Ok(())
-    }
-
-    pub fn upsert_commits(&self, commits: &[Commit]) -> Result<()> {
-        let mut stmt = self.transaction.prepare(
-            "INSERT INTO commits(\"commit\", build_name, build_source, builds, builds_successful, builds_failed, timestamp)
-            VALUES (?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(\"commit\", build_name, build_source) DO UPDATE SET
-                builds = excluded.builds,
-                builds_successful = excluded.builds_successful,
-                builds_failed = excluded.builds_failed,
-                timestamp = excluded.timestamp",
-        )?;
-        for commit in commits {
-            stmt.execute(params![
-                commit.commit,
-                commit.build_name,
-                commit.build_source,
-                commit.builds,
-                commit.builds_successful,
-                commit.builds_failed,
-                commit.timestamp
-            ])?;
-        }
-        Ok(())
-    }
-
-    pub fn insert_import(&self, timestamp: i64) -> Result<()> {
-        let mut stmt = self.transaction.prepare(
-            "INSERT INTO imports(timestamp)
-            VALUES (?)",
-        )?;
-        stmt.execute(params![timestamp])?;
-        Ok(())
-    }
-
-    pub fn insert_hook(&self, hook: &Hook) -> Result<()> {
-        let mut stmt = self.transaction.prepare(
-            "INSERT INTO hooks(timestamp, type, \"commit\")
-            VALUES (?, ?, ?)",
-        )?;
-        stmt.execute(params![hook.timestamp, hook.r#type, hook.commit])?;
-        Ok(())
-    }
-
-    pub fn commit(self) -> Result<()> {
-        self.transaction.commit()?;
-        Ok(())
-    }
-}
+use super::rollups::{self, Dirty};
+use super::Result;
+use super::{functions, repository_path, schema};
+use crate::proto::{BackfillState, Build, Commit, Hook, PurgeRepositoryReply};
+use rusqlite::{params, Connection, InterruptHandle, OpenFlags, OptionalExtension};
+use std::cell::RefCell;
+
+pub struct DB {
+    conn: Connection,
+}
+
+impl DB {
+    pub fn open(directory: &str, repository_id: &str) -> Result<DB> {
+        let path = repository_path(directory, repository_id, "db")?;
+        let mut conn = Connection::open(path)?;
+        // Readers don't block the writer and vice versa. The mode is stored
+        // in the database.
+        conn.execute_batch("PRAGMA journal_mode = WAL")?;
+        // Rollups are maintained with the sketch functions.
+        functions::register(&conn)?;
+        schema::up(&mut conn)?;
+        Ok(DB { conn })
+    }
+
+    /// Opens the database of a repository without creating it.
+    pub fn open_existing(directory: &str, repository_id: &str) -> Result<DB> {
+        let path = repository_path(directory, repository_id, "db")?;
+        let mut conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        functions::register(&conn)?;
+        schema::up(&mut conn)?;
+        Ok(DB { conn })
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.conn.get_interrupt_handle()
+    }
+
+    pub fn transaction(&mut self) -> Result<Transaction> {
+        Ok(Transaction {
+            transaction: self.conn.transaction()?,
+            dirty: RefCell::default(),
+        })
+    }
+}
+
+pub struct Transaction<'conn> {
+    transaction: rusqlite::Transaction<'conn>,
+    // Hours of changed builds and commits, whose rollups are recomputed on
+    // commit.
+    dirty: RefCell<Dirty>,
+}
+
+// Imports of single commits don't always know the branch of a commit, so an
+// empty branch or pull request never overwrites a known one.
+impl Transaction<'_> {
+    pub fn upsert_builds(&self, builds: &[Build]) -> Result<()> {
+        let mut stmt = self.transaction.prepare(
+            "INSERT INTO builds(\"commit\", name, source, timestamp, successful, failed, duration_ms, branch, pull_request, workflow_name, event, attempt, queue_ms, queued_at, outcome)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(\"commit\", name, source, timestamp) DO UPDATE SET
+                successful = excluded.successful,
+                failed = excluded.failed,
+                outcome = excluded.outcome,
+                duration_ms = excluded.duration_ms,
+                branch = CASE WHEN excluded.branch = '' THEN branch ELSE excluded.branch END,
+                pull_request = CASE WHEN excluded.pull_request = 0 THEN pull_request ELSE excluded.pull_request END,
+                workflow_name = CASE WHEN excluded.workflow_name = '' THEN workflow_name ELSE excluded.workflow_name END,
+                event = CASE WHEN excluded.event = '' THEN event ELSE excluded.event END,
+                attempt = CASE WHEN excluded.attempt = 0 THEN attempt ELSE excluded.attempt END,
+                queue_ms = CASE WHEN excluded.queue_ms = 0 THEN queue_ms ELSE excluded.queue_ms END,
+                queued_at = CASE WHEN excluded.queued_at = 0 THEN queued_at ELSE excluded.queued_at END",
+        )?;
+        let mut dirty = self.dirty.borrow_mut();
+        for build in builds {
+            dirty.mark("builds", build.timestamp);
+            stmt.execute(params![
+                build.commit,
+                build.name,
+                build.source,
+                build.timestamp,
+                build.successful,
+                build.failed,
+                build.duration_ms,
+                build.branch,
+                build.pull_request,
+                build.workflow_name,
+                build.event,
+                build.attempt,
+                build.queue_ms,
+                build.queued_at,
+                build.outcome
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn upsert_commits(&self, commits: &[Commit]) -> Result<()> {
+        let mut stmt = self.transaction.prepare(
+            "INSERT INTO commits(\"commit\", build_name, build_source, builds, builds_successful, builds_failed, timestamp, branch, pull_request)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(\"commit\", build_name, build_source) DO UPDATE SET
+                builds = excluded.builds,
+                builds_successful = excluded.builds_successful,
+                builds_failed = excluded.builds_failed,
+                timestamp = excluded.timestamp,
+                branch = CASE WHEN excluded.branch = '' THEN branch ELSE excluded.branch END,
+                pull_request = CASE WHEN excluded.pull_request = 0 THEN pull_request ELSE excluded.pull_request END",
+        )?;
+        // The timestamp of a commit may change, which changes the rollups of
+        // both the old and the new hour.
+        let mut old_timestamp = self.transaction.prepare(
+            "SELECT timestamp FROM commits WHERE \"commit\" = ? AND build_name = ? AND build_source = ?",
+        )?;
+        let mut dirty = self.dirty.borrow_mut();
+        for commit in commits {
+            let old: Option<i64> = old_timestamp
+                .query_row(
+                    params![commit.commit, commit.build_name, commit.build_source],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(old) = old {
+                dirty.mark("commits", old);
+            }
+            dirty.mark("commits", commit.timestamp);
+            stmt.execute(params![
+                commit.commit,
+                commit.build_name,
+                commit.build_source,
+                commit.builds,
+                commit.builds_successful,
+                commit.builds_failed,
+                commit.timestamp,
+                commit.branch,
+                commit.pull_request
+            ])?;
+        }
+        Ok(())
+    }
+
+    pub fn insert_import(&self, timestamp: i64) -> Result<()> {
+        // A backfill imports several batches with the same timestamp.
+        let mut stmt = self.transaction.prepare(
+            "INSERT OR IGNORE INTO imports(timestamp)
+            VALUES (?)",
+        )?;
+        stmt.execute(params![timestamp])?;
+        Ok(())
+    }
+
+    pub fn upsert_backfill(&self, backfill: &BackfillState) -> Result<()> {
+        let mut stmt = self.transaction.prepare(
+            "INSERT INTO backfill(id, head, branch, cursor, commits, completed)
+            VALUES (0, ?, ?, ?, ?, ?)
+            ON CONFLICT(id) DO UPDATE SET
+                head = excluded.head,
+                branch = excluded.branch,
+                cursor = excluded.cursor,
+                commits = excluded.commits,
+                completed = excluded.completed",
+        )?;
+        stmt.execute(params![
+            backfill.head,
+            backfill.branch,
+            backfill.cursor,
+            backfill.commits,
+            backfill.completed
+        ])?;
+        Ok(())
+    }
+
+    /// Inserts the hook unless a hook with the same delivery was inserted
+    /// before. Returns whether the hook was inserted.
+    pub fn insert_hook(&self, hook: &Hook) -> Result<bool> {
+        let mut stmt = self.transaction.prepare(
+            "INSERT INTO hooks(delivery, timestamp, type, \"commit\", branch, pull_request)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT(delivery) WHERE delivery != '' DO NOTHING",
+        )?;
+        let inserted = stmt.execute(params![
+            hook.delivery,
+            hook.timestamp,
+            hook.r#type,
+            hook.commit,
+            hook.branch,
+            hook.pull_request
+        ])?;
+        Ok(inserted > 0)
+    }
+
+    /// Deletes builds, commits and hooks in the time range [since, until).
+    /// Imports and the backfill state are kept, so purged data isn't imported
+    /// again.
+    pub fn delete_range(&self, since: i64, until: i64) -> Result<PurgeRepositoryReply> {
+        let builds = self.transaction.execute(
+            "DELETE FROM builds WHERE timestamp >= ? AND timestamp < ?",
+            params![since, until],
+        )?;
+        let commits = self.transaction.execute(
+            "DELETE FROM commits WHERE timestamp >= ? AND timestamp < ?",
+            params![since, until],
+        )?;
+        let hooks = self.transaction.execute(
+            "DELETE FROM hooks WHERE timestamp >= ? AND timestamp < ?",
+            params![since, until],
+        )?;
+        let mut dirty = self.dirty.borrow_mut();
+        dirty.mark_range(&self.transaction, "builds", since, until)?;
+        dirty.mark_range(&self.transaction, "commits", since, until)?;
+        Ok(PurgeRepositoryReply {
+            builds: builds as i64,
+            commits: commits as i64,
+            hooks: hooks as i64,
+        })
+    }
+
+    pub fn commit(self) -> Result<()> {
+        rollups::refresh(&self.transaction, &self.dirty.borrow())?;
+        self.transaction.commit()?;
+        Ok(())
+    }
+}
@@ -1,18 +1,30 @@
+mod auth;
 mod config;
 mod ctrlc;
 mod db;
 mod health;
+mod purge;
 mod query;
 mod store;
 mod telemetry_service;
 
+use chrono::Utc;
+use db::executor::{Executor, Interrupter, Interruptible};
+use db::pool::{Pool, Pooled};
 use ghss_tracing::init_tracer;
 use health::{HealthServer, HealthService};
+use opentelemetry::api::Context;
 use proto::query_server::QueryServer;
 use proto::store_server::StoreServer;
+use proto::{AuditLogEntry, RepositoryStatus};
+use rusqlite::InterruptHandle;
+use std::collections::HashMap;
 use std::convert::From;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::Duration;
 use telemetry_service::TelemetryServiceExt;
-use tonic::{transport::Server, Code, Status};
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::{Code, Status};
 
 pub(crate) mod proto {
     tonic::include_proto!("ghss.store");
@@ -22,26 +34,226 @@ impl From<db::Error> for Status {
     fn from(err: db::Error) -> Self {
         match err {
             db::Error::DBNotFound => Status::new(Code::FailedPrecondition, "DB not found"),
+            db::Error::UnsupportedSchemaVersion(version) => Status::new(
+                Code::FailedPrecondition,
+                format!("DB schema version {} is newer than supported", version),
+            ),
             db::Error::InvalidIdentifier(_)
+            | db::Error::InvalidRepositoryId(_)
             | db::Error::InvalidTimeRange
-            | db::Error::EmptyColumns => Status::new(Code::InvalidArgument, format!("{:?}", err)),
+            | db::Error::InvalidPercentile(_)
+            | db::Error::InvalidFilter(_)
+            | db::Error::InvalidTimezone(_)
+            | db::Error::TooManyIntervals
+            | db::Error::EmptyColumns
+            | db::Error::MissingAuditInfo => {
+                Status::new(Code::InvalidArgument, format!("{:?}", err))
+            }
+            db::Error::NoImport => Status::new(Code::FailedPrecondition, "No import yet"),
+            db::Error::RepositoryInactive => {
+                Status::new(Code::NotFound, "Repository is not active")
+            }
+            db::Error::RepositoryPurged => {
+                Status::new(Code::FailedPrecondition, "Repository data was deleted")
+            }
+            db::Error::IncompleteImport => {
+                Status::new(Code::InvalidArgument, "Import stream ended without finish")
+            }
+            db::Error::ImportTooLarge => {
+                Status::new(Code::ResourceExhausted, "Import exceeds the size limit")
+            }
+            db::Error::Interrupted => Status::new(Code::Cancelled, "Database call was interrupted"),
             db::Error::SQLite(err) => Status::new(Code::Internal, format!("SQL error: {}", err)),
+            db::Error::IO(err) => Status::new(Code::Internal, format!("IO error: {}", err)),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+type ReadDB = Interruptible<Pooled<db::read::DB>>;
+type WriteDB = Interruptible<Pooled<db::write::DB>>;
+
+/// Interrupts the database work of a call when dropped, i.e. when the call
+/// times out or the client disconnects. Does nothing once the work is done.
+struct InterruptOnDrop(Arc<Interrupter>);
+
+impl Drop for InterruptOnDrop {
+    fn drop(&mut self) {
+        self.0.interrupt();
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct SQLiteStore {
     pub database_directory: String,
+    // Lifecycle lock of each repository currently in use. Writers hold a
+    // read lock while they use a repository database, so it can't be deleted
+    // under them.
+    lifecycles: Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>,
+    // Opened once, since it's used by almost every call. Its queries are
+    // short, so calls take turns.
+    registry: Arc<Mutex<db::registry::Registry>>,
+    readers: Arc<Pool<db::read::DB>>,
+    writers: Arc<Pool<db::write::DB>>,
+    executor: Arc<Executor>,
+    call_timeout: Duration,
+    import_max_rows: usize,
+    import_max_bytes: usize,
+    // Set for the work of a call, which can then be interrupted.
+    interrupter: Option<Arc<Interrupter>>,
 }
 
 impl SQLiteStore {
-    fn db_write(&self, repository_id: String) -> db::Result<db::write::DB> {
-        db::write::DB::open(&self.database_directory, &repository_id)
+    fn new(config: &config::Config) -> db::Result<SQLiteStore> {
+        let registry = db::registry::Registry::open(&config.database_directory)?;
+        Ok(SQLiteStore {
+            database_directory: config.database_directory.clone(),
+            lifecycles: Arc::new(Mutex::new(HashMap::new())),
+            registry: Arc::new(Mutex::new(registry)),
+            readers: Pool::new("readers", config.database_pool_size),
+            writers: Pool::exclusive("writers", config.database_pool_size),
+            executor: Arc::new(Executor::new(config.database_threads)),
+            call_timeout: config.database_call_timeout,
+            import_max_rows: config.import_max_rows,
+            import_max_bytes: config.import_max_bytes,
+            interrupter: None,
+        })
+    }
+
+    /// Runs blocking database work on the database threads, so it doesn't
+    /// stall other requests. The work is part of the current span. It's
+    /// interrupted if it takes longer than the call timeout or the call is
+    /// cancelled.
+    async fn blocking<T, F>(&self, work: F) -> Result<T, Status>
+    where
+        F: FnOnce(&SQLiteStore) -> db::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let interrupter = Arc::new(Interrupter::default());
+        let _interrupt = InterruptOnDrop(interrupter.clone());
+        let store = SQLiteStore {
+            interrupter: Some(interrupter),
+            ..self.clone()
+        };
+        let cx = Context::current();
+        let result = self.executor.run(move || {
+            let _guard = cx.attach();
+            work(&store)
+        });
+        match tokio::time::timeout(self.call_timeout, result).await {
+            Ok(Ok(result)) => result.map_err(Status::from),
+            Ok(Err(_)) => Err(Status::new(Code::Internal, "Database work panicked")),
+            Err(_) => Err(Status::new(
+                Code::DeadlineExceeded,
+                "Database work timed out",
+            )),
+        }
     }
 
-    fn db_read(&self, repository_id: String) -> db::Result<db::read::DB> {
-        db::read::DB::open(&self.database_directory, &repository_id)
+    /// Lets the work of the current call interrupt statements on the
+    /// database.
+    fn interruptible<T>(&self, db: T, handle: InterruptHandle) -> db::Result<Interruptible<T>> {
+        Interruptible::new(db, handle, self.interrupter.as_ref())
+    }
+
+    /// Opens the database of a repository for writing, creating it if
+    /// necessary. Data of deleted repositories isn't written again until the
+    /// repository is re-added.
+    fn db_write(&self, repository_id: String) -> db::Result<WriteDB> {
+        if self.registry().get_status(&repository_id)? == Some(RepositoryStatus::Purged) {
+            return Err(db::Error::RepositoryPurged);
+        }
+        let db = self.writers.get(&repository_id, || {
+            db::write::DB::open(&self.database_directory, &repository_id)
+        })?;
+        let handle = db.interrupt_handle();
+        self.interruptible(db, handle)
+    }
+
+    fn db_read(&self, repository_id: String) -> db::Result<ReadDB> {
+        let db = self.readers.get(&repository_id, || {
+            db::read::DB::open(&self.database_directory, &repository_id)
+        })?;
+        let handle = db.interrupt_handle();
+        self.interruptible(db, handle)
+    }
+
+    /// Opens the database of a repository for queries, which are only
+    /// allowed for active repositories.
+    fn db_query(&self, repository_id: String) -> db::Result<ReadDB> {
+        if !self.registry().is_active(&repository_id)? {
+            return Err(db::Error::RepositoryInactive);
+        }
+        self.db_read(repository_id)
+    }
+
+    fn registry(&self) -> MutexGuard<'_, db::registry::Registry> {
+        self.registry.lock().unwrap()
+    }
+
+    fn audit_log(&self) -> db::Result<db::audit::AuditLog> {
+        db::audit::AuditLog::open(&self.database_directory)
+    }
+
+    /// Returns the lifecycle lock of a repository. Destructive actions hold
+    /// it for writing, so they only block writers of the same repository.
+    fn lifecycle(&self, repository_id: &str) -> Arc<RwLock<()>> {
+        let mut lifecycles = self.lifecycles.lock().unwrap();
+        if let Some(lifecycle) = lifecycles.get(repository_id) {
+            return lifecycle.clone();
+        }
+        // Forget the locks nobody holds, so the map only contains
+        // repositories in use.
+        lifecycles.retain(|_, lifecycle| Arc::strong_count(lifecycle) > 1);
+        lifecycles
+            .entry(repository_id.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    /// Runs a destructive action on repository data and records it in the
+    /// audit log. The action has exclusive access to the databases of the
+    /// repository.
+    fn audited<T>(
+        &self,
+        entry: AuditLogEntry,
+        action: impl FnOnce() -> db::Result<T>,
+    ) -> db::Result<T> {
+        let lifecycle = self.lifecycle(&entry.repository_id);
+        let _lifecycle = lifecycle.write().unwrap();
+        let audit_log = self.audit_log()?;
+        let id = audit_log.start(&entry)?;
+        let res = action();
+        let result = match &res {
+            Ok(_) => "succeeded".to_owned(),
+            Err(err) => format!("{:?}", err),
+        };
+        audit_log.finish(id, &result)?;
+        res
+    }
+
+    /// Deletes all data of a repository and marks it as purged.
+    fn delete_repository(
+        &self,
+        repository_id: &str,
+        reason: &str,
+        requested_by: &str,
+    ) -> db::Result<()> {
+        let now = Utc::now().timestamp_millis();
+        let entry = AuditLogEntry {
+            timestamp: now,
+            action: "delete".to_owned(),
+            repository_id: repository_id.to_owned(),
+            reason: reason.to_owned(),
+            requested_by: requested_by.to_owned(),
+            ..Default::default()
+        };
+        self.audited(entry, || {
+            self.registry()
+                .set_status(repository_id, RepositoryStatus::Purged, now)?;
+            self.readers.evict(repository_id);
+            self.writers.evict(repository_id);
+            db::delete(&self.database_directory, repository_id)
+        })
     }
 }
 
@@ -52,14 +264,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     init_tracer("store", config.otel_agent_endpoint.as_deref())?;
 
     let health_service = HealthService::default();
-    let store = SQLiteStore {
-        database_directory: config.database_directory,
-    };
+    let store = SQLiteStore::new(&config)
+        .unwrap_or_else(|err| panic!("failed to open registry: {:?}", err));
+    let authenticator = config
+        .auth_token_secret
+        .map(|secret| Arc::new(auth::Authenticator::new(secret.as_bytes())));
+
+    if let Some(retention_days) = config.repository_retention_days {
+        tokio::spawn(purge::purge_removed_repositories(
+            store.clone(),
+            retention_days,
+        ));
+    }
+
+    tokio::spawn(db::pool::evict_idle_databases(
+        store.readers.clone(),
+        store.writers.clone(),
+        config.database_max_idle,
+    ));
+
+    let mut server = Server::builder();
+    if let Some(tls) = config.tls {
+        let certificate = std::fs::read(tls.certificate_path)?;
+        let key = std::fs::read(tls.key_path)?;
+        let mut tls_config = ServerTlsConfig::new().identity(Identity::from_pem(certificate, key));
+        if let Some(client_ca_path) = tls.client_ca_path {
+            tls_config =
+                tls_config.client_ca_root(Certificate::from_pem(std::fs::read(client_ca_path)?));
+        }
+        server = server.tls_config(tls_config)?;
+    }
 
-    Server::builder()
+    server
         .add_service(HealthServer::new(health_service).with_telemetry())
-        .add_service(StoreServer::new(store.clone()).with_telemetry())
-        .add_service(QueryServer::new(store).with_telemetry())
+        .add_service(
+            StoreServer::with_interceptor(
+                store.clone(),
+                auth::interceptor(authenticator.clone(), auth::SCOPE_STORE),
+            )
+            .with_telemetry(),
+        )
+        .add_service(
+            QueryServer::with_interceptor(
+                store,
+                auth::interceptor(authenticator, auth::SCOPE_QUERY),
+            )
+            .with_telemetry(),
+        )
         .serve_with_shutdown(([0, 0, 0, 0], 50051).into(), async {
             ctrlc::ctrl_c().await;
         })
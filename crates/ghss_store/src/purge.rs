@@ -0,0 +1,43 @@
+use crate::SQLiteStore;
+use chrono::{Duration, Utc};
+use ghss_tracing::log_event;
+use opentelemetry::api::{Context, FutureExt, StatusCode, TraceContextExt, Tracer};
+use tonic::Status;
+
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Deletes the databases of repositories that were removed more than
+/// `retention_days` ago. Runs forever.
+pub async fn purge_removed_repositories(store: SQLiteStore, retention_days: i64) {
+    let tracer = opentelemetry::global::tracer("store");
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let span = tracer.start("purge removed repositories");
+        let cx = Context::current_with_span(span);
+        if let Err(err) = purge(&store, retention_days).with_context(cx.clone()).await {
+            cx.span()
+                .set_status(StatusCode::Internal, err.message().to_owned());
+        }
+    }
+}
+
+/// Deletes the repositories one by one on the database threads, so the
+/// deletion neither blocks the runtime nor holds a database thread long.
+async fn purge(store: &SQLiteStore, retention_days: i64) -> Result<(), Status> {
+    let removed_before = (Utc::now() - Duration::days(retention_days)).timestamp_millis();
+    let repository_ids = store
+        .blocking(move |store| store.registry().get_removed_before(removed_before))
+        .await?;
+    for repository_id in repository_ids {
+        store
+            .blocking(move |store| {
+                let reason = format!("removed for more than {} days", retention_days);
+                store.delete_repository(&repository_id, &reason, "store")?;
+                log_event(format!("purged {}", repository_id));
+                Ok(())
+            })
+            .await?;
+    }
+    Ok(())
+}
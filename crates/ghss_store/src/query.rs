@@ -1,41 +1,59 @@
-use crate::proto::{
-    query_server::Query, IntervalAggregatesReply, IntervalAggregatesRequest, TotalAggregatesReply,
-    TotalAggregatesRequest,
-};
-use crate::SQLiteStore;
-use tonic::{Request, Response, Status};
-
-#[tonic::async_trait]
-impl Query for SQLiteStore {
-    async fn get_total_aggregates(
-        &self,
-        request: Request<TotalAggregatesRequest>,
-    ) -> Result<Response<TotalAggregatesReply>, Status> {
-        let request = request.into_inner();
-        let db = self.db_read(request.repository_id)?;
-        Ok(Response::new(db.get_total_aggregates(
-            request.table,
-            request.columns,
-            request.since,
-            request.until,
-            request.group_by,
-        )?))
-    }
-
-    async fn get_interval_aggregates(
-        &self,
-        request: Request<IntervalAggregatesRequest>,
-    ) -> Result<Response<IntervalAggregatesReply>, Status> {
-        let request = request.into_inner();
-        let interval = request.interval();
-        let db = self.db_read(request.repository_id)?;
-        Ok(Response::new(db.get_interval_aggregates(
-            request.table,
-            request.columns,
-            request.since,
-            request.until,
-            request.group_by,
-            interval,
-        )?))
-    }
-}
+use crate::auth;
+use crate::db;
+use crate::proto::{
+    query_server::Query, FlakyBuildsReply, FlakyBuildsRequest, IntervalAggregatesReply,
+    IntervalAggregatesRequest, TotalAggregatesReply, TotalAggregatesRequest,
+};
+use crate::SQLiteStore;
+use tonic::{Request, Response, Status};
+
+#[tonic::async_trait]
+impl Query for SQLiteStore {
+    async fn get_total_aggregates(
+        &self,
+        request: Request<TotalAggregatesRequest>,
+    ) -> Result<Response<TotalAggregatesReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        let reply = self
+            .blocking(move |store| {
+                let db = store.db_query(request.repository_id.clone())?;
+                db.get_total_aggregates(request)
+            })
+            .await?;
+        Ok(Response::new(reply))
+    }
+
+    async fn get_interval_aggregates(
+        &self,
+        request: Request<IntervalAggregatesRequest>,
+    ) -> Result<Response<IntervalAggregatesReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        let reply = self
+            .blocking(move |store| {
+                let db = store.db_query(request.repository_id.clone())?;
+                db.get_interval_aggregates(request)
+            })
+            .await?;
+        Ok(Response::new(reply))
+    }
+
+    async fn get_flaky_builds(
+        &self,
+        request: Request<FlakyBuildsRequest>,
+    ) -> Result<Response<FlakyBuildsReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        let reply = self
+            .blocking(move |store| {
+                let db = store.db_query(request.repository_id.clone())?;
+                db.get_flaky_builds(request)
+            })
+            .await?;
+        Ok(Response::new(reply))
+    }
+}
@@ -1,55 +1,394 @@
-use crate::proto::{
-    store_server::Store, HookedCommitsReply, HookedCommitsRequest, ImportReply, ImportRequest,
-    RecordHookReply, RecordHookRequest,
-};
-use crate::SQLiteStore;
-use tonic::{Code, Request, Response, Status};
-
-#[tonic::async_trait]
-impl Store for SQLiteStore {
-    async fn import(
-        &self,
-        request: Request<ImportRequest>,
-    ) -> Result<Response<ImportReply>, Status> {
-        let request = request.into_inner();
-        let mut db = self.db_write(request.repository_id)?;
-        let trx = db.transaction()?;
-        trx.upsert_builds(&request.builds)?;
-        trx.upsert_commits(&request.commits)?;
-        trx.insert_import(request.timestamp)?;
-        trx.commit()?;
-        Ok(Response::new(ImportReply {}))
-    }
-
-    async fn record_hook(
-        &self,
-        request: Request<RecordHookRequest>,
-    ) -> Result<Response<RecordHookReply>, Status> {
-        let request = request.into_inner();
-        let mut db = self.db_write(request.repository_id)?;
-        let trx = db.transaction()?;
-        if let Some(hook) = request.hook {
-            trx.insert_hook(&hook)?;
-        } else {
-            return Err(Status::new(
-                Code::InvalidArgument,
-                "Hook is a mandatory field",
-            ));
-        }
-        if let Some(build) = request.build {
-            trx.upsert_builds(&[build])?;
-        }
-        trx.commit()?;
-        Ok(Response::new(RecordHookReply {}))
-    }
-
-    async fn get_hooked_commits_since_last_import(
-        &self,
-        request: Request<HookedCommitsRequest>,
-    ) -> Result<Response<HookedCommitsReply>, Status> {
-        let request = request.into_inner();
-        let db = self.db_read(request.repository_id)?;
-        let commits = db.get_hooked_commits_since_last_import(request.until)?;
-        Ok(Response::new(HookedCommitsReply { commits }))
-    }
-}
+use crate::auth;
+use crate::db;
+use crate::proto::{
+    store_server::Store, AuditLogEntry, AuditLogReply, AuditLogRequest, BackfillStateReply,
+    BackfillStateRequest, DeleteRepositoryReply, DeleteRepositoryRequest, HookedCommitsReply,
+    HookedCommitsRequest, ImportChunk, ImportReply, ImportRequest, PurgeRepositoryReply,
+    PurgeRepositoryRequest, RecordHookReply, RecordHookRequest, RepositoriesReply,
+    RepositoriesRequest, RepositoryStatus, UpdateInstallationReply, UpdateInstallationRequest,
+    UpdateRepositoriesReply, UpdateRepositoriesRequest,
+};
+use crate::SQLiteStore;
+use chrono::Utc;
+use prost::Message;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+const DEFAULT_AUDIT_LOG_LIMIT: u32 = 100;
+
+fn validate_audit_info(reason: &str, requested_by: &str) -> db::Result<()> {
+    if reason.is_empty() || requested_by.is_empty() {
+        return Err(db::Error::MissingAuditInfo);
+    }
+    Ok(())
+}
+
+/// Receives the next chunk of a streamed import, waiting at most `timeout`.
+async fn next_import_chunk(
+    chunks: &mut Streaming<ImportChunk>,
+    timeout: Duration,
+) -> Result<ImportChunk, Status> {
+    match tokio::time::timeout(timeout, chunks.message()).await {
+        Ok(chunk) => Ok(chunk?.ok_or(db::Error::IncompleteImport)?),
+        Err(_) => Err(Status::new(
+            Code::DeadlineExceeded,
+            "Import chunk timed out",
+        )),
+    }
+}
+
+/// Size of a streamed import received so far.
+#[derive(Default)]
+struct ImportSize {
+    rows: usize,
+    bytes: usize,
+}
+
+impl ImportSize {
+    /// Adds a chunk and fails once the import exceeds the limits.
+    fn add(&mut self, chunk: &ImportChunk, max_rows: usize, max_bytes: usize) -> db::Result<()> {
+        self.rows += chunk.builds.len() + chunk.commits.len();
+        self.bytes += chunk.encoded_len();
+        if self.rows > max_rows || self.bytes > max_bytes {
+            return Err(db::Error::ImportTooLarge);
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Store for SQLiteStore {
+    async fn import(
+        &self,
+        request: Request<ImportRequest>,
+    ) -> Result<Response<ImportReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        self.blocking(move |store| {
+            let lifecycle = store.lifecycle(&request.repository_id);
+            let _lifecycle = lifecycle.read().unwrap();
+            let mut db = store.db_write(request.repository_id)?;
+            let trx = db.transaction()?;
+            trx.upsert_builds(&request.builds)?;
+            trx.upsert_commits(&request.commits)?;
+            trx.insert_import(request.timestamp)?;
+            if let Some(backfill) = request.backfill {
+                trx.upsert_backfill(&backfill)?;
+            }
+            trx.commit()
+        })
+        .await?;
+        Ok(Response::new(ImportReply {}))
+    }
+
+    async fn import_stream(
+        &self,
+        mut request: Request<Streaming<ImportChunk>>,
+    ) -> Result<Response<ImportReply>, Status> {
+        // Chunks are collected first and written in one transaction once the
+        // import finished, so no transaction is held while waiting for the
+        // client. The import is limited, since it's held in memory until then.
+        let mut chunk = next_import_chunk(request.get_mut(), self.call_timeout).await?;
+        if chunk.repository_id.is_empty() {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Repository ID is mandatory in the first chunk",
+            ));
+        }
+        let repository_id = chunk.repository_id.clone();
+        auth::authorize_repository(&request, &repository_id)?;
+        db::validate_repository_id(&repository_id)?;
+
+        let mut size = ImportSize::default();
+        let mut builds = Vec::new();
+        let mut commits = Vec::new();
+        let finish = loop {
+            if !chunk.repository_id.is_empty() && chunk.repository_id != repository_id {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "All chunks must be for the same repository",
+                ));
+            }
+            size.add(&chunk, self.import_max_rows, self.import_max_bytes)?;
+            builds.extend(chunk.builds);
+            commits.extend(chunk.commits);
+            if let Some(finish) = chunk.finish {
+                break finish;
+            }
+            chunk = next_import_chunk(request.get_mut(), self.call_timeout).await?;
+        };
+
+        self.blocking(move |store| {
+            let lifecycle = store.lifecycle(&repository_id);
+            let _lifecycle = lifecycle.read().unwrap();
+            let mut db = store.db_write(repository_id)?;
+            let trx = db.transaction()?;
+            trx.upsert_builds(&builds)?;
+            trx.upsert_commits(&commits)?;
+            trx.insert_import(finish.timestamp)?;
+            if let Some(backfill) = finish.backfill {
+                trx.upsert_backfill(&backfill)?;
+            }
+            trx.commit()
+        })
+        .await?;
+        Ok(Response::new(ImportReply {}))
+    }
+
+    async fn record_hook(
+        &self,
+        request: Request<RecordHookRequest>,
+    ) -> Result<Response<RecordHookReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let RecordHookRequest {
+            repository_id,
+            hook,
+            build,
+            builds,
+        } = request.into_inner();
+        let hook = match hook {
+            Some(hook) => hook,
+            None => {
+                return Err(Status::new(
+                    Code::InvalidArgument,
+                    "Hook is a mandatory field",
+                ))
+            }
+        };
+        let inserted = self
+            .blocking(move |store| {
+                let lifecycle = store.lifecycle(&repository_id);
+                let _lifecycle = lifecycle.read().unwrap();
+                let mut db = store.db_write(repository_id)?;
+                let trx = db.transaction()?;
+                if !trx.insert_hook(&hook)? {
+                    return Ok(false);
+                }
+                if let Some(build) = build {
+                    trx.upsert_builds(&[build])?;
+                }
+                trx.upsert_builds(&builds)?;
+                trx.commit()?;
+                Ok(true)
+            })
+            .await?;
+        Ok(Response::new(RecordHookReply {
+            duplicate: !inserted,
+        }))
+    }
+
+    async fn get_hooked_commits_since_last_import(
+        &self,
+        request: Request<HookedCommitsRequest>,
+    ) -> Result<Response<HookedCommitsReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        let commits = self
+            .blocking(move |store| {
+                let db = store.db_read(request.repository_id)?;
+                db.get_hooked_commits_since_last_import(request.until)
+            })
+            .await?;
+        Ok(Response::new(HookedCommitsReply { commits }))
+    }
+
+    async fn get_backfill_state(
+        &self,
+        request: Request<BackfillStateRequest>,
+    ) -> Result<Response<BackfillStateReply>, Status> {
+        auth::authorize_repository(&request, &request.get_ref().repository_id)?;
+        db::validate_repository_id(&request.get_ref().repository_id)?;
+        let request = request.into_inner();
+        let state = self
+            .blocking(move |store| {
+                let db = store.db_read(request.repository_id)?;
+                db.get_backfill_state()
+            })
+            .await?;
+        Ok(Response::new(BackfillStateReply { state }))
+    }
+
+    async fn update_repositories(
+        &self,
+        request: Request<UpdateRepositoriesRequest>,
+    ) -> Result<Response<UpdateRepositoriesReply>, Status> {
+        for repository in &request.get_ref().repositories {
+            auth::authorize_repository(&request, &repository.repository_id)?;
+            db::validate_repository_id(&repository.repository_id)?;
+        }
+        let request = request.into_inner();
+        self.blocking(move |store| {
+            // Deduplicated, since reading the same lock twice can deadlock
+            // with a waiting writer.
+            let lifecycles = request
+                .repositories
+                .iter()
+                .map(|repository| repository.repository_id.as_str())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .map(|repository_id| store.lifecycle(repository_id))
+                .collect::<Vec<_>>();
+            let _lifecycles = lifecycles
+                .iter()
+                .map(|lifecycle| lifecycle.read().unwrap())
+                .collect::<Vec<_>>();
+            store
+                .registry()
+                .upsert_repositories(&request.repositories)?;
+            // Create the databases of new repositories right away, so hooks
+            // and imports don't race to set them up.
+            for repository in &request.repositories {
+                if repository.status() == RepositoryStatus::Active {
+                    store.db_write(repository.repository_id.clone())?;
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        Ok(Response::new(UpdateRepositoriesReply {}))
+    }
+
+    async fn update_installation(
+        &self,
+        request: Request<UpdateInstallationRequest>,
+    ) -> Result<Response<UpdateInstallationReply>, Status> {
+        auth::authorize_all_repositories(&request)?;
+        let request = request.into_inner();
+        if request.status() == RepositoryStatus::Purged {
+            return Err(Status::new(
+                Code::InvalidArgument,
+                "Installations can't be purged",
+            ));
+        }
+        let repository_ids = self
+            .blocking(move |store| {
+                store.registry().update_installation(
+                    request.installation_id,
+                    request.status(),
+                    request.timestamp,
+                )
+            })
+            .await?;
+        Ok(Response::new(UpdateInstallationReply { repository_ids }))
+    }
+
+    async fn get_repositories(
+        &self,
+        request: Request<RepositoriesRequest>,
+    ) -> Result<Response<RepositoriesReply>, Status> {
+        auth::authorize_all_repositories(&request)?;
+        let repositories = self
+            .blocking(|store| store.registry().get_repositories())
+            .await?;
+        Ok(Response::new(RepositoriesReply { repositories }))
+    }
+
+    async fn delete_repository(
+        &self,
+        request: Request<DeleteRepositoryRequest>,
+    ) -> Result<Response<DeleteRepositoryReply>, Status> {
+        auth::authorize_admin(&request)?;
+        let request = request.into_inner();
+        validate_audit_info(&request.reason, &request.requested_by)?;
+        db::validate_repository_id(&request.repository_id)?;
+        self.blocking(move |store| {
+            store.delete_repository(
+                &request.repository_id,
+                &request.reason,
+                &request.requested_by,
+            )
+        })
+        .await?;
+        Ok(Response::new(DeleteRepositoryReply {}))
+    }
+
+    async fn purge_repository(
+        &self,
+        request: Request<PurgeRepositoryRequest>,
+    ) -> Result<Response<PurgeRepositoryReply>, Status> {
+        auth::authorize_admin(&request)?;
+        let PurgeRepositoryRequest {
+            repository_id,
+            since,
+            until,
+            reason,
+            requested_by,
+        } = request.into_inner();
+        validate_audit_info(&reason, &requested_by)?;
+        db::validate_repository_id(&repository_id)?;
+        if since >= until {
+            return Err(db::Error::InvalidTimeRange.into());
+        }
+        let entry = AuditLogEntry {
+            timestamp: Utc::now().timestamp_millis(),
+            action: "purge".to_owned(),
+            repository_id: repository_id.clone(),
+            since,
+            until,
+            reason,
+            requested_by,
+            ..Default::default()
+        };
+        let reply = self
+            .blocking(move |store| {
+                store.audited(entry, || {
+                    let db = store.writers.get(&repository_id, || {
+                        db::write::DB::open_existing(&store.database_directory, &repository_id)
+                    })?;
+                    let handle = db.interrupt_handle();
+                    let mut db = store.interruptible(db, handle)?;
+                    let trx = db.transaction()?;
+                    let reply = trx.delete_range(since, until)?;
+                    trx.commit()?;
+                    Ok(reply)
+                })
+            })
+            .await?;
+        Ok(Response::new(reply))
+    }
+
+    async fn get_audit_log(
+        &self,
+        request: Request<AuditLogRequest>,
+    ) -> Result<Response<AuditLogReply>, Status> {
+        auth::authorize_admin(&request)?;
+        let request = request.into_inner();
+        let limit = if request.limit == 0 {
+            DEFAULT_AUDIT_LOG_LIMIT
+        } else {
+            request.limit
+        };
+        let entries = self
+            .blocking(move |store| store.audit_log()?.get_entries(limit))
+            .await?;
+        Ok(Response::new(AuditLogReply { entries }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::Build;
+
+    #[test]
+    fn limits_the_size_of_streamed_imports() {
+        let chunk = ImportChunk {
+            repository_id: "1".to_owned(),
+            builds: vec![Build::default(); 2],
+            ..Default::default()
+        };
+        let mut size = ImportSize::default();
+        assert!(size.add(&chunk, 4, usize::MAX).is_ok());
+        assert!(size.add(&chunk, 4, usize::MAX).is_ok());
+        let err = Status::from(size.add(&chunk, 4, usize::MAX).unwrap_err());
+        assert_eq!(err.code(), Code::ResourceExhausted);
+
+        let mut size = ImportSize::default();
+        let err = Status::from(size.add(&chunk, usize::MAX, 1).unwrap_err());
+        assert_eq!(err.code(), Code::ResourceExhausted);
+    }
+}
@@ -0,0 +1,78 @@
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::Serialize;
+use std::time::SystemTime;
+
+/// Tokens are created for every call and expire shortly after.
+const TOKEN_LIFETIME_SECS: u64 = 5 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    /// Write access through the `Store` service.
+    Store,
+    /// Read access through the `Query` service.
+    Query,
+    /// Access to all services, including deleting data.
+    Admin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Scope::Store => "store",
+            Scope::Query => "query",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Claims<'a> {
+    exp: u64,
+    sub: &'a str,
+    scopes: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repository_id: Option<&'a str>,
+}
+
+/// Signs bearer tokens for calls to the store with the secret shared with the
+/// store.
+#[derive(Clone)]
+pub struct Credentials {
+    key: EncodingKey,
+    subject: String,
+    scopes: Vec<Scope>,
+    repository_id: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(secret: &[u8], subject: &str, scopes: &[Scope]) -> Credentials {
+        Credentials {
+            key: EncodingKey::from_secret(secret),
+            subject: subject.to_owned(),
+            scopes: scopes.to_vec(),
+            repository_id: None,
+        }
+    }
+
+    /// Returns credentials that only grant access to the given repository.
+    pub fn for_repository(&self, repository_id: &str) -> Credentials {
+        Credentials {
+            repository_id: Some(repository_id.to_owned()),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn token(&self) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("system time after unix epoch")
+            .as_secs();
+        let claims = Claims {
+            exp: now + TOKEN_LIFETIME_SECS,
+            sub: &self.subject,
+            scopes: self.scopes.iter().map(|scope| scope.as_str()).collect(),
+            repository_id: self.repository_id.as_deref(),
+        };
+        encode(&Header::new(Algorithm::HS256), &claims, &self.key)
+    }
+}
@@ -0,0 +1,55 @@
+use crate::{BackfillState, Build, Commit, ImportChunk, ImportFinish, StoreClient};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl StoreClient {
+    /// Streams batches of builds and commits to the store as they are
+    /// fetched, so they're never all held in memory. The import is only
+    /// finished if all batches were fetched.
+    pub async fn import_batches<S>(
+        &mut self,
+        repository_id: String,
+        timestamp: i64,
+        backfill: Option<BackfillState>,
+        batches: S,
+    ) -> Result<(), BoxError>
+    where
+        S: Stream<Item = Result<(Vec<Build>, Vec<Commit>), BoxError>>,
+    {
+        let (mut chunks, received) = mpsc::channel(1);
+        let send = async move {
+            futures::pin_mut!(batches);
+            while let Some(batch) = batches.next().await {
+                let (builds, commits) = batch?;
+                let chunk = ImportChunk {
+                    repository_id: repository_id.clone(),
+                    builds,
+                    commits,
+                    ..Default::default()
+                };
+                if chunks.send(chunk).await.is_err() {
+                    // The store ended the call, its status is returned.
+                    return Ok(());
+                }
+            }
+            let finish = ImportChunk {
+                repository_id,
+                finish: Some(ImportFinish {
+                    timestamp,
+                    backfill,
+                }),
+                ..Default::default()
+            };
+            let _ = chunks.send(finish).await;
+            Ok::<_, BoxError>(())
+        };
+        let (sent, imported) = futures::join!(send, self.import_stream(received));
+        // A failed batch ends the stream without finish, which fails the
+        // import, but the cause is the failed batch.
+        sent?;
+        imported?;
+        Ok(())
+    }
+}
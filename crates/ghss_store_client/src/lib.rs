@@ -1,11 +1,19 @@
-use ghss_github::{CheckRun, CheckRunConclusion};
+pub use auth::{Credentials, Scope};
+use futures::Stream;
 use opentelemetry::api::{
     Context, Extractor, FutureExt, Injector, Key, SpanKind, StatusCode, TraceContextExt, Tracer,
 };
 pub use proto::*;
 use std::convert::TryInto;
-use tonic::{transport::channel::Channel, Request};
-pub use tonic::{Code, Response, Status};
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::Request;
+pub use tonic::{transport::channel::Channel, Code, Response, Status};
+
+mod auth;
+mod import;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 mod proto {
     tonic::include_proto!("ghss.store");
@@ -28,7 +36,7 @@ impl<'a> Extractor for TonicMetadataMapCarrier<'a> {
 }
 
 macro_rules! client_method {
-    ($func:ident, $req_msg:ident, $reply_msg:ident, $service:literal, $method:literal) => {
+    ($func:ident, $req_msg:ty, $reply_msg:ident, $service:literal, $method:literal) => {
         pub async fn $func(&mut self, message: $req_msg) -> Result<Response<$reply_msg>, Status> {
             let tracer = opentelemetry::global::tracer("store_client");
             let span = tracer
@@ -42,6 +50,14 @@ macro_rules! client_method {
                 .start(&tracer);
             let cx = Context::current_with_span(span);
             let mut request = Request::new(message);
+            if let Some(credentials) = &self.credentials {
+                let token = credentials
+                    .token()
+                    .map_err(|err| Status::new(Code::Unauthenticated, err.to_string()))?;
+                let value = MetadataValue::from_str(&format!("Bearer {}", token))
+                    .map_err(|err| Status::new(Code::Unauthenticated, err.to_string()))?;
+                request.metadata_mut().insert("authorization", value);
+            }
             opentelemetry::global::get_http_text_propagator(|propagator| {
                 propagator
                     .inject_context(&cx, &mut TonicMetadataMapCarrier(request.metadata_mut()));
@@ -60,6 +76,7 @@ macro_rules! client_method {
 #[derive(Clone)]
 pub struct StoreClient {
     inner: store_client::StoreClient<Channel>,
+    credentials: Option<Credentials>,
 }
 
 impl StoreClient {
@@ -69,7 +86,23 @@ impl StoreClient {
         D::Error: Into<tonic::codegen::StdError>,
     {
         let inner = store_client::StoreClient::connect(dst).await?;
-        Ok(Self { inner })
+        Ok(Self {
+            inner,
+            credentials: None,
+        })
+    }
+
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: store_client::StoreClient::new(channel),
+            credentials: None,
+        }
+    }
+
+    /// Authenticates all calls with tokens signed with the credentials.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
     }
 
     client_method!(
@@ -80,6 +113,14 @@ impl StoreClient {
         "Import"
     );
 
+    client_method!(
+        import_stream,
+        impl Stream<Item = ImportChunk> + Send + Sync + 'static,
+        ImportReply,
+        "ghss.store.Store",
+        "ImportStream"
+    );
+
     client_method!(
         record_hook,
         RecordHookRequest,
@@ -95,11 +136,68 @@ impl StoreClient {
         "ghss.store.Store",
         "GetHookedCommitsSinceLastImport"
     );
+
+    client_method!(
+        get_backfill_state,
+        BackfillStateRequest,
+        BackfillStateReply,
+        "ghss.store.Store",
+        "GetBackfillState"
+    );
+
+    client_method!(
+        update_repositories,
+        UpdateRepositoriesRequest,
+        UpdateRepositoriesReply,
+        "ghss.store.Store",
+        "UpdateRepositories"
+    );
+
+    client_method!(
+        update_installation,
+        UpdateInstallationRequest,
+        UpdateInstallationReply,
+        "ghss.store.Store",
+        "UpdateInstallation"
+    );
+
+    client_method!(
+        get_repositories,
+        RepositoriesRequest,
+        RepositoriesReply,
+        "ghss.store.Store",
+        "GetRepositories"
+    );
+
+    client_method!(
+        delete_repository,
+        DeleteRepositoryRequest,
+        DeleteRepositoryReply,
+        "ghss.store.Store",
+        "DeleteRepository"
+    );
+
+    client_method!(
+        purge_repository,
+        PurgeRepositoryRequest,
+        PurgeRepositoryReply,
+        "ghss.store.Store",
+        "PurgeRepository"
+    );
+
+    client_method!(
+        get_audit_log,
+        AuditLogRequest,
+        AuditLogReply,
+        "ghss.store.Store",
+        "GetAuditLog"
+    );
 }
 
 #[derive(Clone)]
 pub struct QueryClient {
     inner: query_client::QueryClient<Channel>,
+    credentials: Option<Credentials>,
 }
 
 impl QueryClient {
@@ -109,7 +207,35 @@ impl QueryClient {
         D::Error: Into<tonic::codegen::StdError>,
     {
         let inner = query_client::QueryClient::connect(dst).await?;
-        Ok(Self { inner })
+        Ok(Self {
+            inner,
+            credentials: None,
+        })
+    }
+
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            inner: query_client::QueryClient::new(channel),
+            credentials: None,
+        }
+    }
+
+    /// Authenticates all calls with tokens signed with the credentials.
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Returns a client whose calls only have access to the given
+    /// repository.
+    pub fn for_repository(&self, repository_id: &str) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            credentials: self
+                .credentials
+                .as_ref()
+                .map(|credentials| credentials.for_repository(repository_id)),
+        }
     }
 
     client_method!(
@@ -127,6 +253,49 @@ impl QueryClient {
         "ghss.store.Query",
         "GetIntervalAggregates"
     );
+
+    client_method!(
+        get_flaky_builds,
+        FlakyBuildsRequest,
+        FlakyBuildsReply,
+        "ghss.store.Query",
+        "GetFlakyBuilds"
+    );
+}
+
+/// PEM files for connecting to the store over TLS.
+pub struct TlsConfig {
+    pub ca_certificate_path: String,
+    /// Client certificate and key, if the store requires mutual TLS.
+    pub identity: Option<(String, String)>,
+}
+
+/// Connects to the store. The channel can be shared by the `StoreClient` and
+/// `QueryClient`.
+pub async fn connect_channel(url: String, tls: Option<&TlsConfig>) -> Result<Channel, BoxError> {
+    let mut endpoint = Endpoint::from_shared(url)?;
+    if let Some(tls) = tls {
+        let ca_certificate = std::fs::read(&tls.ca_certificate_path)?;
+        let mut tls_config =
+            ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_certificate));
+        if let Some((certificate_path, key_path)) = &tls.identity {
+            let certificate = std::fs::read(certificate_path)?;
+            let key = std::fs::read(key_path)?;
+            tls_config = tls_config.identity(Identity::from_pem(certificate, key));
+        }
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+    Ok(endpoint.connect().await?)
+}
+
+/// Returns the ID of a GitHub repository in the store. Repositories of GitHub
+/// instances other than github.com are prefixed with the instance name, so
+/// that several instances can share a store.
+pub fn store_repository_id(instance: Option<&str>, repository_id: i32) -> String {
+    match instance {
+        Some(instance) => format!("{}-{}", instance, repository_id),
+        None => repository_id.to_string(),
+    }
 }
 
 fn tonic_to_otel_status(status: &Status) -> StatusCode {
@@ -152,32 +321,3 @@ fn tonic_to_otel_status(status: &Status) -> StatusCode {
         _ => StatusCode::Unknown,
     }
 }
-
-impl From<CheckRun> for Build {
-    fn from(check_run: CheckRun) -> Self {
-        Self {
-            name: check_run.name,
-            source: BuildSource::CheckRun as i32,
-            commit: check_run.head_sha,
-            successful: match &check_run.conclusion {
-                Some(conclusion) => conclusion == &CheckRunConclusion::Success,
-                None => false,
-            },
-            failed: match &check_run.conclusion {
-                Some(conclusion) => {
-                    conclusion == &CheckRunConclusion::Failure
-                        || conclusion == &CheckRunConclusion::TimedOut
-                }
-                None => false,
-            },
-            duration_ms: match check_run.completed_at {
-                Some(completed_at) => (completed_at.timestamp_millis()
-                    - check_run.started_at.timestamp_millis())
-                .try_into()
-                .expect("duration should fit into u32"),
-                None => 0,
-            },
-            timestamp: check_run.started_at.timestamp_millis(),
-        }
-    }
-}
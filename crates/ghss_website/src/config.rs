@@ -1,14 +1,35 @@
+use ghss_github::{instance_from_env, Urls};
+use ghss_store_client::TlsConfig;
 use secstr::{SecStr, SecUtf8};
 
 pub struct Config {
     pub host: String,
+    pub gh_urls: Urls,
+    /// Name of the GitHub instance used to namespace repositories in the
+    /// store. Not set for github.com.
+    pub gh_instance: Option<String>,
     pub cookie_name: &'static str,
     pub gh_redirect_uri: String,
     pub gh_client_id: String,
     pub gh_client_secret: SecUtf8,
-    pub gh_webhook_secret: SecStr,
+    /// Active webhook secrets. Deliveries signed with any of them are
+    /// accepted, so the secret can be rotated without dropping deliveries.
+    pub gh_webhook_secrets: Vec<SecStr>,
+    /// GitHub App credentials used to fetch the statuses of hooked commits
+    /// right away. Without them, statuses are only imported by the importer.
+    pub gh_app_id: Option<String>,
+    pub gh_private_key: Option<SecUtf8>,
     pub store_url: String,
+    /// Secret shared with the store to sign bearer tokens.
+    pub store_token_secret: Option<SecUtf8>,
+    pub store_tls: Option<TlsConfig>,
     pub token_secret: SecStr,
+    /// SQLite database holding webhooks until they're processed.
+    pub hook_queue_path: String,
+    /// Attempts to process a webhook before it's moved to the dead letters.
+    pub hook_max_attempts: u32,
+    /// Bearer token for the admin endpoints. They're disabled if not set.
+    pub admin_token: Option<SecStr>,
     pub otel_agent_endpoint: Option<String>,
 }
 
@@ -20,18 +41,73 @@ fn option_env(name: &str) -> Option<String> {
     std::env::var(name).ok()
 }
 
+fn parse_env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    option_env(name)
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("env {} invalid", name))
+        })
+        .unwrap_or(default)
+}
+
+// E.g. 0 attempts would move every hook to the dead letters right away.
+fn parse_nonzero_env(name: &str, default: u32) -> u32 {
+    let value = parse_env(name, default);
+    if value == 0 {
+        panic!("env {} invalid", name);
+    }
+    value
+}
+
+fn load_gh_webhook_secrets() -> Vec<SecStr> {
+    let mut secrets: Vec<SecStr> = option_env("GH_WEBHOOK_SECRET")
+        .into_iter()
+        .chain(
+            option_env("GH_WEBHOOK_SECRETS")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|secret| secret.to_owned()),
+        )
+        .filter(|secret| !secret.is_empty())
+        .map(SecStr::from)
+        .collect();
+    secrets.dedup();
+    if secrets.is_empty() {
+        panic!("env GH_WEBHOOK_SECRET or GH_WEBHOOK_SECRETS required");
+    }
+    secrets
+}
+
+fn load_store_tls() -> Option<TlsConfig> {
+    option_env("STORE_TLS_CA_PATH").map(|ca_certificate_path| TlsConfig {
+        ca_certificate_path,
+        identity: option_env("STORE_TLS_CERTIFICATE_PATH")
+            .map(|certificate_path| (certificate_path, env("STORE_TLS_KEY_PATH"))),
+    })
+}
+
 pub fn load() -> Config {
     let host = env("HOST");
     let gh_redirect_uri = format!("{}/setup/authorized", host);
     Config {
         host,
+        gh_urls: Urls::from_env(),
+        gh_instance: instance_from_env(),
         cookie_name: "token",
         gh_redirect_uri,
         gh_client_id: env("GH_CLIENT_ID"),
         gh_client_secret: SecUtf8::from(env("GH_CLIENT_SECRET")),
-        gh_webhook_secret: SecStr::from(env("GH_WEBHOOK_SECRET")),
+        gh_webhook_secrets: load_gh_webhook_secrets(),
+        gh_app_id: option_env("GH_APP_ID"),
+        gh_private_key: option_env("GH_PRIVATE_KEY").map(SecUtf8::from),
         store_url: env("STORE_URL"),
+        store_token_secret: option_env("STORE_TOKEN_SECRET").map(SecUtf8::from),
+        store_tls: load_store_tls(),
         token_secret: SecStr::from(env("TOKEN_SECRET")),
+        hook_queue_path: option_env("HOOK_QUEUE_PATH").unwrap_or_else(|| "hooks.db".to_owned()),
+        hook_max_attempts: parse_nonzero_env("HOOK_MAX_ATTEMPTS", 10),
+        admin_token: option_env("ADMIN_TOKEN").map(SecStr::from),
         otel_agent_endpoint: option_env("OTEL_AGENT_ENDPOINT"),
     }
 }
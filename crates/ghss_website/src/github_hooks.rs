@@ -1,43 +1,76 @@
-use ghss_github::{CheckRunEvent, GitHubAppAuthorizationEvent, PingEvent, StatusEvent};
-use hmac::{Hmac, Mac, NewMac};
+use ghss_github::{
+    CheckRunEvent, GitHubAppAuthorizationEvent, InstallationEvent, InstallationRepositoriesEvent,
+    PingEvent, StatusEvent, WorkflowJobEvent, WorkflowRunEvent,
+};
+use hmac::{
+    crypto_mac::generic_array::ArrayLength,
+    digest::{BlockInput, FixedOutput, Reset, Update},
+    Hmac, Mac, NewMac,
+};
 use secstr::SecStr;
 use sha1::Sha1;
+use sha2::Sha256;
 
-type BoxError = Box<dyn std::error::Error>;
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[derive(Debug)]
 pub enum Payload {
     CheckRun(Box<CheckRunEvent>),
     GitHubAppAuthorization(Box<GitHubAppAuthorizationEvent>),
-    Installation,
-    InstallationRepositories,
+    Installation(Box<InstallationEvent>),
+    InstallationRepositories(Box<InstallationRepositoriesEvent>),
     Ping(Box<PingEvent>),
     Status(Box<StatusEvent>),
+    WorkflowJob(Box<WorkflowJobEvent>),
+    WorkflowRun(Box<WorkflowRunEvent>),
 }
 
-fn validate_signature(
-    signature: impl Into<Vec<u8>>,
+fn verify<D>(expected: &str, body: &[u8], secret: &[u8]) -> bool
+where
+    D: Update + BlockInput + FixedOutput + Reset + Default + Clone,
+    D::BlockSize: ArrayLength<u8>,
+{
+    let expected = match hex::decode(expected) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<D>::new_varkey(secret).expect("HMAC can take key of any size");
+    mac.update(body);
+    // Compares in constant time.
+    mac.verify(&expected).is_ok()
+}
+
+/// Validates the webhook signature against each of the active secrets. The
+/// SHA-256 signature is preferred if GitHub sent it.
+pub fn validate_signature(
+    signature_256: Option<&str>,
+    signature: Option<&str>,
     body: &[u8],
-    secret: &[u8],
+    secrets: &[SecStr],
 ) -> Result<(), BoxError> {
-    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("HMAC can take key of any size");
-    mac.update(body);
-    let result = SecStr::from(format!("sha1={:x}", mac.finalize().into_bytes()));
-    if result == SecStr::from(signature) {
+    let valid = match (signature_256, signature) {
+        (Some(signature_256), _) => match signature_256.strip_prefix("sha256=") {
+            Some(expected) => secrets
+                .iter()
+                .any(|secret| verify::<Sha256>(expected, body, secret.unsecure())),
+            None => false,
+        },
+        (None, Some(signature)) => match signature.strip_prefix("sha1=") {
+            Some(expected) => secrets
+                .iter()
+                .any(|secret| verify::<Sha1>(expected, body, secret.unsecure())),
+            None => false,
+        },
+        (None, None) => return Err(From::from("Signature missing".to_string())),
+    };
+    if valid {
         Ok(())
     } else {
         Err(From::from("Signature doesn't match".to_string()))
     }
 }
 
-pub fn deserialize(
-    signature: impl Into<Vec<u8>>,
-    event: &str,
-    body: &[u8],
-    secret: &[u8],
-) -> Result<Payload, BoxError> {
-    validate_signature(signature, body, secret)?;
-
+pub fn deserialize(event: &str, body: &[u8]) -> Result<Payload, BoxError> {
     match event {
         "check_run" => Ok(serde_json::from_slice::<CheckRunEvent>(body)
             .map(|data| Payload::CheckRun(Box::new(data)))?),
@@ -51,12 +84,88 @@ pub fn deserialize(
         }
         "status" => Ok(serde_json::from_slice::<StatusEvent>(body)
             .map(|data| Payload::Status(Box::new(data)))?),
-        "installation" => Ok(Payload::Installation),
+        "workflow_job" => Ok(serde_json::from_slice::<WorkflowJobEvent>(body)
+            .map(|data| Payload::WorkflowJob(Box::new(data)))?),
+        "workflow_run" => Ok(serde_json::from_slice::<WorkflowRunEvent>(body)
+            .map(|data| Payload::WorkflowRun(Box::new(data)))?),
         // integration_installation is deprecated; replaced by installation
-        "integration_installation" => Ok(Payload::Installation),
-        "installation_repositories" => Ok(Payload::InstallationRepositories),
+        "installation" | "integration_installation" => {
+            Ok(serde_json::from_slice::<InstallationEvent>(body)
+                .map(|data| Payload::Installation(Box::new(data)))?)
+        }
         // integration_installation_repositories is deprecated; replaced by installation_repositories
-        "integration_installation_repositories" => Ok(Payload::InstallationRepositories),
+        "installation_repositories" | "integration_installation_repositories" => Ok(
+            serde_json::from_slice::<InstallationRepositoriesEvent>(body)
+                .map(|data| Payload::InstallationRepositories(Box::new(data)))?,
+        ),
         _ => Err(From::from(format!("Unsupported event {}", event))),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vector of the GitHub webhook documentation. The documentation
+    // only gives the SHA-256 signature; the SHA-1 one was computed for it.
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SHA256: &str = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+    const SHA1: &str = "sha1=01dc10d0c83e72ed246219cdd91669667fe2ca59";
+
+    // Synthetic ping body, signed with a made-up rotated secret.
+    const ROTATED_SECRET: &str = "rotated-secret";
+    const PING_BODY: &[u8] = br#"{"zen":"Design for failure.","hook_id":12345678}"#;
+    const PING_SHA256: &str =
+        "sha256=6dac5f591ff195cc8c80c37c9f095d905f71ff0ea08c264043f3bbf0ad9d2a0b";
+    const PING_SHA1: &str = "sha1=c9c587f518849303cb76e5c79d48d5fb3ae94963";
+
+    fn to_secrets(secrets: &[&str]) -> Vec<SecStr> {
+        secrets.iter().map(|secret| SecStr::from(*secret)).collect()
+    }
+
+    #[test]
+    fn accepts_sha256() {
+        let secrets = to_secrets(&[SECRET]);
+        assert!(validate_signature(Some(SHA256), Some(SHA1), BODY, &secrets).is_ok());
+        assert!(validate_signature(Some(SHA256), None, BODY, &secrets).is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_sha256_without_falling_back_to_sha1() {
+        let secrets = to_secrets(&[SECRET]);
+        let wrong = "sha256=0000000000000000000000000000000000000000000000000000000000000000";
+        assert!(validate_signature(Some(wrong), Some(SHA1), BODY, &secrets).is_err());
+        assert!(validate_signature(Some(SHA256), Some(SHA1), b"Hello, World?", &secrets).is_err());
+    }
+
+    #[test]
+    fn falls_back_to_sha1() {
+        let secrets = to_secrets(&[SECRET]);
+        assert!(validate_signature(None, Some(SHA1), BODY, &secrets).is_ok());
+        assert!(validate_signature(None, Some(SHA1), b"Hello, World?", &secrets).is_err());
+    }
+
+    #[test]
+    fn accepts_any_rotated_secret() {
+        let secrets = to_secrets(&[SECRET, ROTATED_SECRET]);
+        assert!(validate_signature(Some(PING_SHA256), None, PING_BODY, &secrets).is_ok());
+        assert!(validate_signature(None, Some(PING_SHA1), PING_BODY, &secrets).is_ok());
+        assert!(validate_signature(Some(SHA256), None, BODY, &secrets).is_ok());
+
+        let retired = to_secrets(&[SECRET]);
+        assert!(validate_signature(Some(PING_SHA256), None, PING_BODY, &retired).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_or_malformed_prefixes() {
+        let secrets = to_secrets(&[SECRET]);
+        assert!(validate_signature(None, None, BODY, &secrets).is_err());
+        let unprefixed = SHA256.trim_start_matches("sha256=");
+        assert!(validate_signature(Some(unprefixed), Some(SHA1), BODY, &secrets).is_err());
+        let swapped = format!("sha1={}", unprefixed);
+        assert!(validate_signature(Some(&swapped), None, BODY, &secrets).is_err());
+        assert!(validate_signature(None, Some(&SHA1[5..]), BODY, &secrets).is_err());
+        assert!(validate_signature(Some("sha256=not hex"), None, BODY, &secrets).is_err());
+    }
+}
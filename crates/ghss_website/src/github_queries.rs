@@ -1,8 +1,16 @@
+use chrono::{Duration, Utc};
 use futures::future::join_all;
-use ghss_github::{Client, Installation, Repository, User};
+use ghss_builds::{statuses_to_builds, stream_builds};
+use ghss_github::{Client, Installation, Repository, Urls, User};
+use ghss_store_client::{Build, StoreClient};
+use secstr::SecUtf8;
+use std::collections::HashMap;
+use std::sync::Mutex;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
+const INITIAL_IMPORT_CONCURRENT_COMMITS: usize = 8;
+
 pub struct GitHubUser {
     pub user: User,
     pub repositories: Vec<Repository>,
@@ -18,8 +26,8 @@ async fn get_repositories_and_map_error(
         .map_err(|err| err.to_string())
 }
 
-pub async fn get_github_user(token: &str) -> Result<GitHubUser, BoxError> {
-    let client = Client::new(token)?;
+pub async fn get_github_user(urls: &Urls, token: &str) -> Result<GitHubUser, BoxError> {
+    let client = Client::new(token)?.with_base_urls(urls.clone());
     let user = client.get_user().await?;
     let installations = client.get_user_installations().await?;
     let repositories = join_all(
@@ -36,3 +44,104 @@ pub async fn get_github_user(token: &str) -> Result<GitHubUser, BoxError> {
 
     Ok(GitHubUser { user, repositories })
 }
+
+struct CachedToken {
+    token: String,
+    expires_at: chrono::DateTime<Utc>,
+}
+
+/// Creates clients authenticated as an installation of the GitHub App.
+/// Installation access tokens are valid for an hour and reused until shortly
+/// before they expire.
+pub struct InstallationClients {
+    app_id: String,
+    private_key: SecUtf8,
+    urls: Urls,
+    tokens: Mutex<HashMap<i32, CachedToken>>,
+}
+
+impl InstallationClients {
+    pub fn new(app_id: String, private_key: SecUtf8, urls: Urls) -> InstallationClients {
+        InstallationClients {
+            app_id,
+            private_key,
+            urls,
+            tokens: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn client(&self, installation_id: i32) -> Result<Client, BoxError> {
+        let cached = self
+            .tokens
+            .lock()
+            .unwrap()
+            .get(&installation_id)
+            .filter(|cached| cached.expires_at > Utc::now() + Duration::minutes(5))
+            .map(|cached| cached.token.clone());
+        let token = match cached {
+            Some(token) => token,
+            None => {
+                let app_client = Client::new_app_auth(&self.app_id, self.private_key.unsecure())?
+                    .with_base_urls(self.urls.clone());
+                let access_token = app_client
+                    .create_app_installation_access_token(installation_id)
+                    .await?;
+                self.tokens.lock().unwrap().insert(
+                    installation_id,
+                    CachedToken {
+                        token: access_token.token.clone(),
+                        expires_at: access_token.expires_at.with_timezone(&Utc),
+                    },
+                );
+                access_token.token
+            }
+        };
+        Ok(Client::new(&token)?.with_base_urls(self.urls.clone()))
+    }
+}
+
+/// Fetches all statuses of a commit and reconstructs its builds the same way
+/// as the importer.
+pub async fn get_status_builds(
+    installations: &InstallationClients,
+    installation_id: i32,
+    repository: &Repository,
+    commit_sha: &str,
+) -> Result<Vec<Build>, BoxError> {
+    let client = installations.client(installation_id).await?;
+    let statuses = client
+        .get_statuses(&repository.owner.login, &repository.name, commit_sha)
+        .await?;
+    Ok(statuses_to_builds(statuses, commit_sha))
+}
+
+/// Imports the most recent builds of a newly added repository, so its
+/// dashboard has data before the next run of the importer.
+pub async fn import_new_repository(
+    installations: &InstallationClients,
+    mut store_client: StoreClient,
+    installation_id: i32,
+    repository_id: String,
+    full_name: &str,
+) -> Result<(), BoxError> {
+    let (owner, name) = full_name
+        .split_once('/')
+        .ok_or_else(|| format!("Invalid repository name {}", full_name))?;
+    let client = installations.client(installation_id).await?;
+    let commits = client.get_most_recent_commits(owner, name).await?;
+    store_client
+        .import_batches(
+            repository_id,
+            Utc::now().timestamp_millis(),
+            None,
+            stream_builds(
+                &client,
+                owner,
+                name,
+                commits,
+                INITIAL_IMPORT_CONCURRENT_COMMITS,
+            ),
+        )
+        .await?;
+    Ok(())
+}
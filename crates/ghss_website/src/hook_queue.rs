@@ -0,0 +1,193 @@
+use rusqlite::{params, Connection, OptionalExtension, Result, NO_PARAMS};
+use serde::Serialize;
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS queue (
+        id              INTEGER PRIMARY KEY AUTOINCREMENT,
+        event           TEXT NOT NULL,
+        delivery        TEXT NOT NULL,
+        payload         BLOB NOT NULL,
+        received_at     INTEGER NOT NULL,
+        attempts        INTEGER NOT NULL DEFAULT 0,
+        next_attempt_at INTEGER NOT NULL,
+        last_error      TEXT
+    );
+    CREATE INDEX IF NOT EXISTS queue_next_attempt_at ON queue(next_attempt_at);
+    CREATE TABLE IF NOT EXISTS dead_letters (
+        id          INTEGER PRIMARY KEY,
+        event       TEXT NOT NULL,
+        delivery    TEXT NOT NULL,
+        payload     BLOB NOT NULL,
+        received_at INTEGER NOT NULL,
+        attempts    INTEGER NOT NULL,
+        last_error  TEXT NOT NULL,
+        failed_at   INTEGER NOT NULL
+    );";
+
+/// First retry delay. Doubles with every attempt up to `MAX_RETRY_DELAY_MS`.
+const RETRY_DELAY_MS: i64 = 10_000;
+const MAX_RETRY_DELAY_MS: i64 = 3_600_000;
+
+pub struct QueuedHook {
+    pub id: i64,
+    pub event: String,
+    pub delivery: String,
+    pub payload: Vec<u8>,
+    pub attempts: u32,
+}
+
+#[derive(Serialize)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub event: String,
+    pub delivery: String,
+    pub received_at: i64,
+    pub attempts: u32,
+    pub last_error: String,
+    pub failed_at: i64,
+}
+
+/// Webhook payloads that were received but not processed yet, persisted in a
+/// SQLite database so they survive restarts and store outages.
+pub struct HookQueue {
+    conn: Mutex<Connection>,
+}
+
+impl HookQueue {
+    pub fn open(path: &str) -> Result<HookQueue> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", &"WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(HookQueue {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn enqueue(&self, event: &str, delivery: &str, payload: &[u8], now: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO queue(event, delivery, payload, received_at, next_attempt_at)
+            VALUES (?, ?, ?, ?, ?)",
+            params![event, delivery, payload, now, now],
+        )?;
+        Ok(())
+    }
+
+    /// Returns hooks ready to be processed, oldest first.
+    pub fn due(&self, now: i64, limit: u32) -> Result<Vec<QueuedHook>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, event, delivery, payload, attempts
+            FROM queue
+            WHERE next_attempt_at <= ?
+            ORDER BY id
+            LIMIT ?",
+        )?;
+        let hooks = stmt
+            .query_map(params![now, limit], |row| {
+                Ok(QueuedHook {
+                    id: row.get(0)?,
+                    event: row.get(1)?,
+                    delivery: row.get(2)?,
+                    payload: row.get(3)?,
+                    attempts: row.get(4)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        Ok(hooks)
+    }
+
+    pub fn complete(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM queue WHERE id = ?", params![id])?;
+        Ok(())
+    }
+
+    /// Schedules a retry of the hook with exponential backoff, or moves it to
+    /// the dead letters once it failed `max_attempts` times. Returns whether
+    /// the hook was dead-lettered.
+    pub fn fail(
+        &self,
+        hook: &QueuedHook,
+        error: &str,
+        now: i64,
+        max_attempts: u32,
+    ) -> Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let attempts = hook.attempts + 1;
+        if attempts >= max_attempts {
+            let trx = conn.transaction()?;
+            trx.execute(
+                "INSERT INTO dead_letters(id, event, delivery, payload, received_at, attempts, last_error, failed_at)
+                SELECT id, event, delivery, payload, received_at, ?, ?, ?
+                FROM queue
+                WHERE id = ?",
+                params![attempts, error, now, hook.id],
+            )?;
+            trx.execute("DELETE FROM queue WHERE id = ?", params![hook.id])?;
+            trx.commit()?;
+            return Ok(true);
+        }
+
+        let delay = RETRY_DELAY_MS
+            .saturating_mul(1 << hook.attempts.min(20))
+            .min(MAX_RETRY_DELAY_MS);
+        conn.execute(
+            "UPDATE queue SET attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?",
+            params![attempts, now + delay, error, hook.id],
+        )?;
+        Ok(false)
+    }
+
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, event, delivery, received_at, attempts, last_error, failed_at
+            FROM dead_letters
+            ORDER BY id",
+        )?;
+        let dead_letters = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok(DeadLetter {
+                    id: row.get(0)?,
+                    event: row.get(1)?,
+                    delivery: row.get(2)?,
+                    received_at: row.get(3)?,
+                    attempts: row.get(4)?,
+                    last_error: row.get(5)?,
+                    failed_at: row.get(6)?,
+                })
+            })?
+            .collect::<Result<_>>()?;
+        Ok(dead_letters)
+    }
+
+    /// Moves a dead letter back into the queue to be processed right away.
+    /// Returns false if there is no dead letter with the ID.
+    pub fn replay(&self, id: i64, now: i64) -> Result<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let trx = conn.transaction()?;
+        let exists = trx
+            .query_row(
+                "SELECT 1 FROM dead_letters WHERE id = ?",
+                params![id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(false);
+        }
+        trx.execute(
+            "INSERT INTO queue(event, delivery, payload, received_at, next_attempt_at)
+            SELECT event, delivery, payload, received_at, ?
+            FROM dead_letters
+            WHERE id = ?",
+            params![now, id],
+        )?;
+        trx.execute("DELETE FROM dead_letters WHERE id = ?", params![id])?;
+        trx.commit()?;
+        Ok(true)
+    }
+}
@@ -2,22 +2,37 @@ mod config;
 mod ctrlc;
 mod github_hooks;
 mod github_queries;
+mod hook_queue;
 mod serve_file;
 mod telemetry_middleware;
 mod templates;
 mod token;
 
+use chrono::Utc;
 use futures::{future::FutureExt as _, select};
+use ghss_builds::{check_run_to_build, workflow_job_to_build, workflow_run_to_build};
+use ghss_github::{
+    InstallationEventAction, InstallationRepositoriesEventAction, InstallationRepository,
+    WorkflowEventAction,
+};
 use ghss_store_client::{
-    AggregateFunction, BuildSource, Hook, IntervalAggregatesRequest, IntervalType, QueryClient,
-    RecordHookRequest, StoreClient, TotalAggregatesRequest,
+    connect_channel, filter, store_repository_id, value, AggregateFunction, BuildSource, Code,
+    ComparisonOperator, Credentials, Filter, FlakyBuildsRequest, Hook, IntervalAggregatesRequest,
+    IntervalType, QueryClient, RecordHookReply, RecordHookRequest, RegisteredRepository,
+    RepositoryStatus, Scope, Status, StoreClient, TotalAggregatesRequest,
+    UpdateInstallationRequest, UpdateRepositoriesRequest,
 };
 use ghss_tracing::{error_event, init_tracer, log_event};
+use github_queries::{get_status_builds, import_new_repository, InstallationClients};
+use hook_queue::{HookQueue, QueuedHook};
+use opentelemetry::api::{Context, FutureExt, Key, TraceContextExt, Tracer};
 use regex::Regex;
+use secstr::SecStr;
 use serde::{Deserialize, Serialize};
 use serve_file::RouteExt;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use telemetry_middleware::TelemetryMiddleware;
 use templates::{DashboardData, DashboardTemplate, IndexTemplate, RepositoryAccess};
 use tide::{
@@ -25,6 +40,12 @@ use tide::{
     Body, Redirect, Request, Response, StatusCode,
 };
 use token::OptionalToken;
+use tokio::sync::Notify;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const HOOK_QUEUE_BATCH_SIZE: u32 = 32;
+const HOOK_QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 struct State {
@@ -32,6 +53,9 @@ struct State {
     templates: Arc<templates::Templates<'static>>,
     store_client: StoreClient,
     query_client: QueryClient,
+    installations: Option<Arc<InstallationClients>>,
+    hook_queue: Arc<HookQueue>,
+    hook_queue_notify: Arc<Notify>,
 }
 
 async fn handle_index(req: Request<State>) -> tide::Result<Response> {
@@ -52,10 +76,16 @@ async fn handle_index(req: Request<State>) -> tide::Result<Response> {
                     .map(|repo| RepositoryAccess { name: repo.name })
                     .collect(),
                 login_url: ghss_github::oauth::login_url(
+                    &config.gh_urls,
                     &config.gh_client_id,
                     &config.gh_redirect_uri,
                     None,
                 ),
+                app_url: config
+                    .gh_urls
+                    .web_endpoint(&["apps", "status-stats-for-github"])
+                    .map(|url| url.to_string())
+                    .unwrap_or_default(),
             };
             Response::builder(200)
                 .body(templates.render_index(&data))
@@ -63,13 +93,18 @@ async fn handle_index(req: Request<State>) -> tide::Result<Response> {
                 .build()
         }
         OptionalToken::Expired => {
-            let login_url =
-                ghss_github::oauth::login_url(&config.gh_client_id, &config.gh_redirect_uri, None);
+            let login_url = ghss_github::oauth::login_url(
+                &config.gh_urls,
+                &config.gh_client_id,
+                &config.gh_redirect_uri,
+                None,
+            );
             Redirect::temporary(login_url).into()
         }
         OptionalToken::None => {
             let data = IndexTemplate::Anonymous {
                 login_url: ghss_github::oauth::login_url(
+                    &config.gh_urls,
                     &config.gh_client_id,
                     &config.gh_redirect_uri,
                     None,
@@ -109,6 +144,11 @@ async fn handle_dashboard(req: Request<State>) -> tide::Result<Response> {
             let mut res: Response = templates
                 .render_dashboard(&DashboardTemplate {
                     user: user.name,
+                    repository_url: config
+                        .gh_urls
+                        .web_endpoint(&[&owner, &repo])
+                        .map(|url| url.to_string())
+                        .unwrap_or_default(),
                     repository_name: name,
                     data,
                 })
@@ -118,6 +158,7 @@ async fn handle_dashboard(req: Request<State>) -> tide::Result<Response> {
         }
         OptionalToken::Expired | OptionalToken::None => {
             let login_url = ghss_github::oauth::login_url(
+                &config.gh_urls,
                 &config.gh_client_id,
                 &config.gh_redirect_uri,
                 Some(path_to_state(format!("/d/{}/{}", owner, repo))),
@@ -132,6 +173,10 @@ async fn handle_dashboard(req: Request<State>) -> tide::Result<Response> {
 enum ApiQueryAggregateFunction {
     Avg,
     Count,
+    Min,
+    Max,
+    Sum,
+    Percentile(f64),
 }
 
 impl From<ApiQueryAggregateFunction> for AggregateFunction {
@@ -139,6 +184,10 @@ impl From<ApiQueryAggregateFunction> for AggregateFunction {
         match function {
             ApiQueryAggregateFunction::Avg => Self::Avg,
             ApiQueryAggregateFunction::Count => Self::Count,
+            ApiQueryAggregateFunction::Min => Self::Min,
+            ApiQueryAggregateFunction::Max => Self::Max,
+            ApiQueryAggregateFunction::Sum => Self::Sum,
+            ApiQueryAggregateFunction::Percentile(_) => Self::Percentile,
         }
     }
 }
@@ -151,9 +200,14 @@ struct ApiQueryColumn {
 
 impl From<ApiQueryColumn> for ghss_store_client::Column {
     fn from(aggregate: ApiQueryColumn) -> Self {
+        let percentile = match aggregate.agg_func {
+            ApiQueryAggregateFunction::Percentile(percentile) => percentile,
+            _ => 0.0,
+        };
         Self {
             name: aggregate.name,
             agg_func: AggregateFunction::from(aggregate.agg_func) as i32,
+            percentile,
         }
     }
 }
@@ -163,6 +217,10 @@ impl From<ApiQueryColumn> for ghss_store_client::Column {
 enum ApiQueryIntervalType {
     Sparse,
     Detailed,
+    Hour,
+    Day,
+    Week,
+    Month,
 }
 
 impl From<ApiQueryIntervalType> for IntervalType {
@@ -170,6 +228,10 @@ impl From<ApiQueryIntervalType> for IntervalType {
         match interval {
             ApiQueryIntervalType::Sparse => Self::Sparse,
             ApiQueryIntervalType::Detailed => Self::Detailed,
+            ApiQueryIntervalType::Hour => Self::Hour,
+            ApiQueryIntervalType::Day => Self::Day,
+            ApiQueryIntervalType::Week => Self::Week,
+            ApiQueryIntervalType::Month => Self::Month,
         }
     }
 }
@@ -187,18 +249,24 @@ impl<'de> serde::de::Visitor<'de> for VecApiQueryAggregateVisitor {
     where
         E: serde::de::Error,
     {
-        let re = Regex::new(r"(avg|count)\(([A-Za-z0-9_]+)\)").unwrap();
+        let re =
+            Regex::new(r"(avg|count|min|max|sum|p(\d+(?:\.\d+)?))\(([A-Za-z0-9_]+)\)").unwrap();
         v.split(',')
             .filter(|part| !part.is_empty())
             .map(|part| {
                 re.captures(part)
                     .ok_or_else(|| E::custom("invalid aggregate value"))
                     .map(|cap| ApiQueryColumn {
-                        name: cap[2].into(),
+                        name: cap[3].into(),
                         agg_func: match &cap[1] {
                             "avg" => ApiQueryAggregateFunction::Avg,
                             "count" => ApiQueryAggregateFunction::Count,
-                            _ => panic!("invalid aggregate function"),
+                            "min" => ApiQueryAggregateFunction::Min,
+                            "max" => ApiQueryAggregateFunction::Max,
+                            "sum" => ApiQueryAggregateFunction::Sum,
+                            _ => ApiQueryAggregateFunction::Percentile(
+                                cap[2].parse().expect("regex should only match numbers"),
+                            ),
                         },
                     })
             })
@@ -251,6 +319,85 @@ where
     deserializer.deserialize_option(OptionVecStringVisitor)
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ApiQueryFilterValue {
+    Int(i64),
+    Double(f64),
+    String(String),
+}
+
+impl From<ApiQueryFilterValue> for ghss_store_client::Value {
+    fn from(value: ApiQueryFilterValue) -> Self {
+        Self {
+            kind: Some(match value {
+                ApiQueryFilterValue::Int(v) => value::Kind::Integer(v),
+                ApiQueryFilterValue::Double(v) => value::Kind::Real(v),
+                ApiQueryFilterValue::String(v) => value::Kind::Text(v),
+            }),
+        }
+    }
+}
+
+/// Filter expression, passed as JSON in the `filter` query parameter, e.g.
+/// `{"and":[{"eq":["name","ci/build"]},{"in":["source",[0,1]]}]}`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ApiQueryFilter {
+    Eq(String, ApiQueryFilterValue),
+    Ne(String, ApiQueryFilterValue),
+    Lt(String, ApiQueryFilterValue),
+    Le(String, ApiQueryFilterValue),
+    Gt(String, ApiQueryFilterValue),
+    Ge(String, ApiQueryFilterValue),
+    In(String, Vec<ApiQueryFilterValue>),
+    And(Vec<ApiQueryFilter>),
+    Or(Vec<ApiQueryFilter>),
+}
+
+fn comparison(column: String, op: ComparisonOperator, value: ApiQueryFilterValue) -> filter::Expr {
+    filter::Expr::Comparison(filter::Comparison {
+        column,
+        op: op as i32,
+        value: Some(value.into()),
+    })
+}
+
+fn filter_list(filters: Vec<ApiQueryFilter>) -> filter::FilterList {
+    filter::FilterList {
+        filters: filters.into_iter().map(|f| f.into()).collect(),
+    }
+}
+
+impl From<ApiQueryFilter> for Filter {
+    fn from(filter: ApiQueryFilter) -> Self {
+        let expr = match filter {
+            ApiQueryFilter::Eq(c, v) => comparison(c, ComparisonOperator::Eq, v),
+            ApiQueryFilter::Ne(c, v) => comparison(c, ComparisonOperator::Ne, v),
+            ApiQueryFilter::Lt(c, v) => comparison(c, ComparisonOperator::Lt, v),
+            ApiQueryFilter::Le(c, v) => comparison(c, ComparisonOperator::Le, v),
+            ApiQueryFilter::Gt(c, v) => comparison(c, ComparisonOperator::Gt, v),
+            ApiQueryFilter::Ge(c, v) => comparison(c, ComparisonOperator::Ge, v),
+            ApiQueryFilter::In(column, values) => filter::Expr::InList(filter::InList {
+                column,
+                values: values.into_iter().map(|v| v.into()).collect(),
+            }),
+            ApiQueryFilter::And(filters) => filter::Expr::And(filter_list(filters)),
+            ApiQueryFilter::Or(filters) => filter::Expr::Or(filter_list(filters)),
+        };
+        Self { expr: Some(expr) }
+    }
+}
+
+fn deserialize_option_filter<'de, D>(deserializer: D) -> Result<Option<ApiQueryFilter>, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let raw: Option<String> = Deserialize::deserialize(deserializer)?;
+    raw.map(|raw| serde_json::from_str(&raw).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
 #[derive(Debug, Deserialize)]
 struct ApiQueryParams {
     repository: i32,
@@ -262,6 +409,9 @@ struct ApiQueryParams {
     #[serde(default, deserialize_with = "deserialize_option_strings")]
     group_by: Option<Vec<String>>,
     interval: Option<ApiQueryIntervalType>,
+    timezone: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_option_filter")]
+    filter: Option<ApiQueryFilter>,
 }
 
 #[derive(Debug, Serialize)]
@@ -277,11 +427,26 @@ struct ApiQueryResponse {
     series: Vec<ApiQueryResponseSeries>,
 }
 
+/// Responds to a failed store query. Queries of inactive repositories are
+/// answered with 404, so the dashboard can tell them apart from errors.
+fn query_error_response(err: Box<dyn std::error::Error>) -> Response {
+    match err.downcast_ref::<Status>() {
+        Some(status) if status.code() == Code::NotFound => StatusCode::NotFound.into(),
+        _ => {
+            error_event("query failed", err.as_ref());
+            StatusCode::InternalServerError.into()
+        }
+    }
+}
+
 async fn handle_api_query(req: Request<State>) -> tide::Result<Response> {
     let state = req.state();
     let config = &state.config;
-    let mut client = state.query_client.clone();
     let params: ApiQueryParams = req.query()?;
+    let mut client = state.query_client.for_repository(&store_repository_id(
+        config.gh_instance.as_deref(),
+        params.repository,
+    ));
     let res: Response = match token::optional_token(
         &req,
         config.cookie_name,
@@ -295,13 +460,18 @@ async fn handle_api_query(req: Request<State>) -> tide::Result<Response> {
                     Some(interval) => {
                         let response = client
                             .get_interval_aggregates(IntervalAggregatesRequest {
-                                repository_id: params.repository.to_string(),
+                                repository_id: store_repository_id(
+                                    config.gh_instance.as_deref(),
+                                    params.repository,
+                                ),
                                 table: params.table,
                                 columns: params.columns.into_iter().map(|c| c.into()).collect(),
                                 since: params.since,
                                 until: params.until,
                                 group_by: params.group_by.unwrap_or_default(),
                                 interval: IntervalType::from(interval) as i32,
+                                filter: params.filter.map(|f| f.into()),
+                                timezone: params.timezone.unwrap_or_default(),
                             })
                             .await?
                             .into_inner();
@@ -311,6 +481,9 @@ async fn handle_api_query(req: Request<State>) -> tide::Result<Response> {
                             if timestamps.last() != Some(&row.timestamp) {
                                 timestamps.push(row.timestamp);
                             }
+                            if row.values.is_empty() {
+                                continue;
+                            }
 
                             let values: &mut Vec<Option<Vec<f64>>> =
                                 series.entry(row.groups).or_default();
@@ -332,12 +505,16 @@ async fn handle_api_query(req: Request<State>) -> tide::Result<Response> {
                     None => {
                         let response = client
                             .get_total_aggregates(TotalAggregatesRequest {
-                                repository_id: params.repository.to_string(),
+                                repository_id: store_repository_id(
+                                    config.gh_instance.as_deref(),
+                                    params.repository,
+                                ),
                                 table: params.table,
                                 columns: params.columns.into_iter().map(|c| c.into()).collect(),
                                 since: params.since,
                                 until: params.until,
                                 group_by: params.group_by.unwrap_or_default(),
+                                filter: params.filter.map(|f| f.into()),
                             })
                             .await?
                             .into_inner();
@@ -360,10 +537,87 @@ async fn handle_api_query(req: Request<State>) -> tide::Result<Response> {
             .await;
             match res {
                 Ok(res) => Body::from_json(&res)?.into(),
-                Err(err) => {
-                    error_event("query failed", err.as_ref());
-                    StatusCode::InternalServerError.into()
-                }
+                Err(err) => query_error_response(err),
+            }
+        }
+        _ => StatusCode::Unauthorized.into(),
+    };
+    Ok(res)
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiFlakyParams {
+    repository: i32,
+    since: i64,
+    until: i64,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiFlakyBuild {
+    name: String,
+    source: &'static str,
+    commits: u32,
+    flaky_commits: Vec<String>,
+    flakiness: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiFlakyResponse {
+    builds: Vec<ApiFlakyBuild>,
+}
+
+async fn handle_api_flaky(req: Request<State>) -> tide::Result<Response> {
+    let state = req.state();
+    let config = &state.config;
+    let params: ApiFlakyParams = req.query()?;
+    let mut client = state.query_client.for_repository(&store_repository_id(
+        config.gh_instance.as_deref(),
+        params.repository,
+    ));
+    let res: Response = match token::optional_token(
+        &req,
+        config.cookie_name,
+        config.token_secret.unsecure().into(),
+    ) {
+        OptionalToken::Some(user)
+            if user.repositories.iter().any(|r| r.id == params.repository) =>
+        {
+            let res: Result<ApiFlakyResponse, Box<dyn std::error::Error>> = async {
+                let response = client
+                    .get_flaky_builds(FlakyBuildsRequest {
+                        repository_id: store_repository_id(
+                            config.gh_instance.as_deref(),
+                            params.repository,
+                        ),
+                        since: params.since,
+                        until: params.until,
+                        limit: params.limit.unwrap_or_default(),
+                    })
+                    .await?
+                    .into_inner();
+                let builds = response
+                    .builds
+                    .into_iter()
+                    .map(|build| ApiFlakyBuild {
+                        source: match build.source() {
+                            BuildSource::Status => "status",
+                            BuildSource::CheckRun => "check_run",
+                            BuildSource::WorkflowRun => "workflow_run",
+                            BuildSource::WorkflowJob => "workflow_job",
+                        },
+                        name: build.name,
+                        commits: build.commits,
+                        flaky_commits: build.flaky_commits,
+                        flakiness: build.flakiness,
+                    })
+                    .collect();
+                Ok(ApiFlakyResponse { builds })
+            }
+            .await;
+            match res {
+                Ok(res) => Body::from_json(&res)?.into(),
+                Err(err) => query_error_response(err),
             }
         }
         _ => StatusCode::Unauthorized.into(),
@@ -377,13 +631,19 @@ async fn handle_setup_authorized(req: Request<State>) -> tide::Result<Response>
     let info: ghss_github::oauth::AuthCodeQuery = req.query()?;
     let token = async {
         let github_token = ghss_github::oauth::exchange_code(
+            &config.gh_urls,
             &config.gh_client_id,
             &config.gh_client_secret.unsecure(),
             &config.gh_redirect_uri,
             &info,
         )
         .await?;
-        token::generate(&github_token.access_token, config.token_secret.unsecure()).await
+        token::generate(
+            &config.gh_urls,
+            &github_token.access_token,
+            config.token_secret.unsecure(),
+        )
+        .await
     }
     .await;
 
@@ -419,64 +679,425 @@ async fn handle_hooks(mut req: Request<State>) -> tide::Result<Response> {
     let body = req.body_bytes().await?;
     let state = req.state();
     let config = &state.config;
-    let mut client = state.store_client.clone();
-    let signature = req.header("X-Hub-Signature").ok_or_else(|| {
-        tide::Error::from_str(StatusCode::BadRequest, "X-Hub-Signature header required")
-    })?;
+    let signature_256 = req
+        .header("X-Hub-Signature-256")
+        .map(|signature| signature.as_str());
+    let signature = req
+        .header("X-Hub-Signature")
+        .map(|signature| signature.as_str());
+    if signature_256.is_none() && signature.is_none() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "X-Hub-Signature-256 or X-Hub-Signature header required",
+        ));
+    }
     let event = req.header("X-GitHub-Event").ok_or_else(|| {
         tide::Error::from_str(StatusCode::BadRequest, "X-GitHub-Event header required")
     })?;
-    let res: Result<(), Box<dyn std::error::Error>> = async {
-        let payload = github_hooks::deserialize(
-            signature.as_str(),
-            event.as_str(),
-            &body,
-            &config.gh_webhook_secret.unsecure(),
-        )?;
-
-        log_event(format!("hook payload: {:?}", payload));
-
-        match payload {
-            github_hooks::Payload::CheckRun(check_run) => {
-                let _response = client
-                    .record_hook(RecordHookRequest {
-                        repository_id: check_run.repository.id.to_string(),
-                        hook: Some(Hook {
-                            r#type: BuildSource::CheckRun as i32,
-                            commit: check_run.check_run.head_sha.clone(),
-                            timestamp: check_run.check_run.started_at.timestamp_millis(),
-                        }),
-                        build: Some(check_run.check_run.into()),
-                    })
-                    .await?;
+    let delivery = req
+        .header("X-GitHub-Delivery")
+        .map(|delivery| delivery.as_str())
+        .unwrap_or_default();
+
+    if let Err(err) = github_hooks::validate_signature(
+        signature_256,
+        signature,
+        &body,
+        &config.gh_webhook_secrets,
+    ) {
+        error_event("hook rejected", err.as_ref());
+        return Ok(StatusCode::Unauthorized.into());
+    }
+
+    // Reject payloads that can never be processed instead of retrying them.
+    if let Err(err) = github_hooks::deserialize(event.as_str(), &body) {
+        error_event("hook rejected", err.as_ref());
+        return Ok(StatusCode::BadRequest.into());
+    }
+
+    let res = match state.hook_queue.enqueue(
+        event.as_str(),
+        delivery,
+        &body,
+        Utc::now().timestamp_millis(),
+    ) {
+        Ok(()) => {
+            state.hook_queue_notify.notify();
+            StatusCode::Accepted.into()
+        }
+        Err(err) => {
+            error_event("enqueueing hook failed", &err);
+            StatusCode::InternalServerError.into()
+        }
+    };
+    Ok(res)
+}
+
+fn log_duplicate(reply: &RecordHookReply) {
+    if reply.duplicate {
+        log_event("hook delivery recorded before".to_owned());
+    }
+}
+
+fn registered_repositories(
+    config: &config::Config,
+    installation_id: i32,
+    repositories: &[InstallationRepository],
+    status: RepositoryStatus,
+) -> Vec<RegisteredRepository> {
+    let now = Utc::now().timestamp_millis();
+    repositories
+        .iter()
+        .map(|repository| RegisteredRepository {
+            repository_id: store_repository_id(config.gh_instance.as_deref(), repository.id),
+            installation_id: installation_id.into(),
+            full_name: repository.full_name.clone(),
+            status: status as i32,
+            updated_at: now,
+        })
+        .collect()
+}
+
+/// Registers new repositories and imports their most recent builds in the
+/// background. The importer catches up on anything the initial import misses.
+async fn add_repositories(
+    state: &State,
+    installation_id: i32,
+    repositories: &[InstallationRepository],
+) -> Result<(), BoxError> {
+    let repositories = registered_repositories(
+        &state.config,
+        installation_id,
+        repositories,
+        RepositoryStatus::Active,
+    );
+    state
+        .store_client
+        .clone()
+        .update_repositories(UpdateRepositoriesRequest {
+            repositories: repositories.clone(),
+        })
+        .await?;
+
+    if let Some(installations) = &state.installations {
+        for repository in repositories {
+            let installations = installations.clone();
+            let store_client = state.store_client.clone();
+            let cx = Context::current();
+            tokio::spawn(
+                async move {
+                    let res = import_new_repository(
+                        &installations,
+                        store_client,
+                        installation_id,
+                        repository.repository_id,
+                        &repository.full_name,
+                    )
+                    .await;
+                    if let Err(err) = res {
+                        error_event("initial import failed", err.as_ref());
+                    }
+                }
+                .with_context(cx),
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn update_installation(
+    state: &State,
+    installation_id: i32,
+    status: RepositoryStatus,
+) -> Result<(), BoxError> {
+    let response = state
+        .store_client
+        .clone()
+        .update_installation(UpdateInstallationRequest {
+            installation_id: installation_id.into(),
+            status: status as i32,
+            timestamp: Utc::now().timestamp_millis(),
+        })
+        .await?;
+    log_event(format!(
+        "{} repositories changed",
+        response.get_ref().repository_ids.len()
+    ));
+    Ok(())
+}
+
+async fn process_hook(state: &State, hook: &QueuedHook) -> Result<(), BoxError> {
+    let config = &state.config;
+    let mut client = state.store_client.clone();
+    let delivery = &hook.delivery;
+    let payload = github_hooks::deserialize(&hook.event, &hook.payload)?;
+
+    log_event(format!("hook payload: {:?}", payload));
+
+    match payload {
+        github_hooks::Payload::CheckRun(check_run) => {
+            let build = check_run_to_build(check_run.check_run);
+            let response = client
+                .record_hook(RecordHookRequest {
+                    repository_id: store_repository_id(
+                        config.gh_instance.as_deref(),
+                        check_run.repository.id,
+                    ),
+                    hook: Some(Hook {
+                        r#type: BuildSource::CheckRun as i32,
+                        commit: build.commit.clone(),
+                        timestamp: build.timestamp,
+                        branch: build.branch.clone(),
+                        pull_request: build.pull_request,
+                        delivery: delivery.clone(),
+                    }),
+                    build: Some(build),
+                    builds: vec![],
+                })
+                .await?;
+            log_duplicate(response.get_ref());
+        }
+        github_hooks::Payload::GitHubAppAuthorization(_auth) => {}
+        github_hooks::Payload::Installation(installation) => {
+            let installation_id = installation.installation.id;
+            match installation.action {
+                InstallationEventAction::Created => {
+                    add_repositories(state, installation_id, &installation.repositories).await?;
+                }
+                InstallationEventAction::Deleted => {
+                    update_installation(state, installation_id, RepositoryStatus::Removed).await?;
+                }
+                InstallationEventAction::Suspend => {
+                    update_installation(state, installation_id, RepositoryStatus::Suspended)
+                        .await?;
+                }
+                InstallationEventAction::Unsuspend => {
+                    update_installation(state, installation_id, RepositoryStatus::Active).await?;
+                }
+                InstallationEventAction::NewPermissionsAccepted => {}
             }
-            github_hooks::Payload::GitHubAppAuthorization(_auth) => {}
-            github_hooks::Payload::Installation => {}
-            github_hooks::Payload::InstallationRepositories => {}
-            github_hooks::Payload::Ping(_ping) => {}
-            github_hooks::Payload::Status(status) => {
-                let _response = client
-                    .record_hook(RecordHookRequest {
-                        repository_id: status.repository.id.to_string(),
-                        hook: Some(Hook {
-                            r#type: BuildSource::Status as i32,
-                            commit: status.sha,
-                            timestamp: status.created_at.timestamp_millis(),
-                        }),
-                        build: None,
-                    })
-                    .await?;
+        }
+        github_hooks::Payload::InstallationRepositories(installation) => {
+            let installation_id = installation.installation.id;
+            match installation.action {
+                InstallationRepositoriesEventAction::Added => {
+                    add_repositories(state, installation_id, &installation.repositories_added)
+                        .await?;
+                }
+                InstallationRepositoriesEventAction::Removed => {
+                    client
+                        .update_repositories(UpdateRepositoriesRequest {
+                            repositories: registered_repositories(
+                                config,
+                                installation_id,
+                                &installation.repositories_removed,
+                                RepositoryStatus::Removed,
+                            ),
+                        })
+                        .await?;
+                }
+            }
+        }
+        github_hooks::Payload::Ping(_ping) => {}
+        github_hooks::Payload::Status(status) => {
+            let branch = status
+                .branches
+                .first()
+                .map(|branch| branch.name.clone())
+                .unwrap_or_default();
+            // A failure to fetch the statuses fails the hook, so the queue
+            // retries it before anything is recorded.
+            let mut builds = match (&state.installations, &status.installation) {
+                (Some(installations), Some(installation)) => {
+                    get_status_builds(
+                        installations,
+                        installation.id,
+                        &status.repository,
+                        &status.sha,
+                    )
+                    .await?
+                }
+                _ => vec![],
+            };
+            for build in &mut builds {
+                build.branch = branch.clone();
+            }
+            let response = client
+                .record_hook(RecordHookRequest {
+                    repository_id: store_repository_id(
+                        config.gh_instance.as_deref(),
+                        status.repository.id,
+                    ),
+                    hook: Some(Hook {
+                        r#type: BuildSource::Status as i32,
+                        commit: status.sha,
+                        timestamp: status.created_at.timestamp_millis(),
+                        branch,
+                        pull_request: 0,
+                        delivery: delivery.clone(),
+                    }),
+                    build: None,
+                    builds,
+                })
+                .await?;
+            log_duplicate(response.get_ref());
+        }
+        github_hooks::Payload::WorkflowJob(workflow_job) => {
+            // Builds are keyed by their start time, which may change until a
+            // job completes, so only completed jobs are recorded. Jobs are
+            // named after their workflow, which older GitHub versions don't
+            // include in the payload. The importer picks those up instead.
+            let record_build = workflow_job.action == WorkflowEventAction::Completed
+                && workflow_job.workflow_job.workflow_name.is_some();
+            let build = workflow_job_to_build(workflow_job.workflow_job);
+            let response = client
+                .record_hook(RecordHookRequest {
+                    repository_id: store_repository_id(
+                        config.gh_instance.as_deref(),
+                        workflow_job.repository.id,
+                    ),
+                    hook: Some(Hook {
+                        r#type: BuildSource::WorkflowJob as i32,
+                        commit: build.commit.clone(),
+                        timestamp: build.timestamp,
+                        branch: build.branch.clone(),
+                        pull_request: build.pull_request,
+                        delivery: delivery.clone(),
+                    }),
+                    build: Some(build).filter(|_| record_build),
+                    builds: vec![],
+                })
+                .await?;
+            log_duplicate(response.get_ref());
+        }
+        github_hooks::Payload::WorkflowRun(workflow_run) => {
+            let build = workflow_run_to_build(workflow_run.workflow_run);
+            let response = client
+                .record_hook(RecordHookRequest {
+                    repository_id: store_repository_id(
+                        config.gh_instance.as_deref(),
+                        workflow_run.repository.id,
+                    ),
+                    hook: Some(Hook {
+                        r#type: BuildSource::WorkflowRun as i32,
+                        commit: build.commit.clone(),
+                        timestamp: build.timestamp,
+                        branch: build.branch.clone(),
+                        pull_request: build.pull_request,
+                        delivery: delivery.clone(),
+                    }),
+                    build: Some(build),
+                    builds: vec![],
+                })
+                .await?;
+            log_duplicate(response.get_ref());
+        }
+    };
+
+    Ok(())
+}
+
+/// Processes queued hooks in the order they were received. Failed hooks are
+/// retried with backoff until they're dead-lettered.
+async fn process_hook_queue(state: State) {
+    let tracer = opentelemetry::global::tracer("website");
+    loop {
+        let now = Utc::now().timestamp_millis();
+        let hooks = match state.hook_queue.due(now, HOOK_QUEUE_BATCH_SIZE) {
+            Ok(hooks) => hooks,
+            Err(err) => {
+                let span = tracer.start("hook queue");
+                let cx = Context::current_with_span(span);
+                let _guard = cx.attach();
+                error_event("reading hook queue failed", &err);
+                vec![]
             }
         };
 
-        Ok(())
+        if hooks.is_empty() {
+            let _ =
+                tokio::time::timeout(HOOK_QUEUE_POLL_INTERVAL, state.hook_queue_notify.notified())
+                    .await;
+            continue;
+        }
+
+        for hook in hooks {
+            let span = tracer
+                .span_builder("hook")
+                .with_attributes(vec![
+                    Key::new("hook.event").string(hook.event.clone()),
+                    Key::new("hook.delivery").string(hook.delivery.clone()),
+                    Key::new("hook.attempt").u64((hook.attempts + 1).into()),
+                ])
+                .start(&tracer);
+            let cx = Context::current_with_span(span);
+            let res = process_hook(&state, &hook).with_context(cx.clone()).await;
+            let _guard = cx.clone().attach();
+            let now = Utc::now().timestamp_millis();
+            let res = match res {
+                Ok(()) => state.hook_queue.complete(hook.id),
+                Err(err) => {
+                    error_event("hook failed", err.as_ref());
+                    cx.span()
+                        .set_status(opentelemetry::api::StatusCode::Internal, err.to_string());
+                    state
+                        .hook_queue
+                        .fail(&hook, &err.to_string(), now, state.config.hook_max_attempts)
+                        .map(|dead_lettered| {
+                            if dead_lettered {
+                                log_event("hook moved to dead letters".to_owned());
+                            }
+                        })
+                }
+            };
+            if let Err(err) = res {
+                error_event("updating hook queue failed", &err);
+            }
+        }
     }
-    .await;
+}
 
-    let res = match res {
-        Ok(_) => StatusCode::Ok.into(),
+fn is_admin(req: &Request<State>) -> bool {
+    match (&req.state().config.admin_token, req.header("Authorization")) {
+        (Some(admin_token), Some(authorization)) => {
+            let mut expected = b"Bearer ".to_vec();
+            expected.extend_from_slice(admin_token.unsecure());
+            SecStr::from(authorization.as_str()) == SecStr::new(expected)
+        }
+        _ => false,
+    }
+}
+
+async fn handle_admin_dead_letters(req: Request<State>) -> tide::Result<Response> {
+    if !is_admin(&req) {
+        return Ok(StatusCode::Unauthorized.into());
+    }
+
+    let res = match req.state().hook_queue.dead_letters() {
+        Ok(dead_letters) => Body::from_json(&dead_letters)?.into(),
+        Err(err) => {
+            error_event("reading dead letters failed", &err);
+            StatusCode::InternalServerError.into()
+        }
+    };
+    Ok(res)
+}
+
+async fn handle_admin_replay_dead_letter(req: Request<State>) -> tide::Result<Response> {
+    if !is_admin(&req) {
+        return Ok(StatusCode::Unauthorized.into());
+    }
+
+    let state = req.state();
+    let id: i64 = req.param("id")?;
+    let res = match state.hook_queue.replay(id, Utc::now().timestamp_millis()) {
+        Ok(true) => {
+            state.hook_queue_notify.notify();
+            StatusCode::Accepted.into()
+        }
+        Ok(false) => StatusCode::NotFound.into(),
         Err(err) => {
-            error_event("hook failed", err.as_ref());
+            error_event("replaying dead letter failed", &err);
             StatusCode::InternalServerError.into()
         }
     };
@@ -509,17 +1130,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let config = config::load();
     let templates = templates::load();
 
-    let store_client = StoreClient::connect(config.store_url.clone()).await?;
-    let query_client = QueryClient::connect(config.store_url.clone()).await?;
+    let channel = connect_channel(config.store_url.clone(), config.store_tls.as_ref()).await?;
+    let mut store_client = StoreClient::new(channel.clone());
+    let mut query_client = QueryClient::new(channel);
+    if let Some(secret) = &config.store_token_secret {
+        let secret = secret.unsecure().as_bytes();
+        store_client =
+            store_client.with_credentials(Credentials::new(secret, "website", &[Scope::Store]));
+        query_client =
+            query_client.with_credentials(Credentials::new(secret, "website", &[Scope::Query]));
+    }
 
     init_tracer("website", config.otel_agent_endpoint.as_deref())?;
 
+    let installations = match (&config.gh_app_id, &config.gh_private_key) {
+        (Some(app_id), Some(private_key)) => Some(Arc::new(InstallationClients::new(
+            app_id.clone(),
+            private_key.clone(),
+            config.gh_urls.clone(),
+        ))),
+        _ => None,
+    };
+
+    let hook_queue = Arc::new(HookQueue::open(&config.hook_queue_path)?);
+    let admin_enabled = config.admin_token.is_some();
+
     let state = State {
         config: Arc::new(config),
         templates: Arc::new(templates),
         store_client,
         query_client,
+        installations,
+        hook_queue,
+        hook_queue_notify: Arc::new(Notify::new()),
     };
+    tokio::spawn(process_hook_queue(state.clone()));
 
     let mut app = tide::with_state(state);
     app.at("/").get(handle_index);
@@ -527,9 +1172,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     app.at("/static").serve_dir("static")?;
     app.at("/d/:owner/:repo").get(handle_dashboard);
     app.at("/api/query").get(handle_api_query);
+    app.at("/api/flaky").get(handle_api_flaky);
     app.at("/setup/authorized").get(handle_setup_authorized);
     app.at("/logout").get(handle_logout);
     app.at("/hooks").post(handle_hooks);
+    if admin_enabled {
+        app.at("/admin/hooks/dead-letters")
+            .get(handle_admin_dead_letters);
+        app.at("/admin/hooks/dead-letters/:id/replay")
+            .post(handle_admin_replay_dead_letter);
+    }
 
     app.with(TelemetryMiddleware {});
 
@@ -15,6 +15,7 @@ pub enum IndexTemplate {
         user: String,
         repositories: Vec<RepositoryAccess>,
         login_url: String,
+        app_url: String,
     },
 }
 
@@ -28,6 +29,7 @@ pub enum DashboardData {
 pub struct DashboardTemplate {
     pub user: String,
     pub repository_name: String,
+    pub repository_url: String,
     pub data: DashboardData,
 }
 
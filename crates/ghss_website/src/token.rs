@@ -1,4 +1,5 @@
 use super::github_queries::{get_github_user, GitHubUser};
+use ghss_github::Urls;
 use ghss_tracing::error_event;
 use jsonwebtoken::{
     decode, encode, errors::Error as TokenError, Algorithm, DecodingKey, EncodingKey, Header,
@@ -29,8 +30,12 @@ struct Claims {
     repositories: Vec<RepositoryClaim>,
 }
 
-pub async fn generate(github_token: &str, secret: &[u8]) -> Result<String, BoxError> {
-    let GitHubUser { user, repositories } = get_github_user(github_token).await?;
+pub async fn generate(
+    gh_urls: &Urls,
+    github_token: &str,
+    secret: &[u8],
+) -> Result<String, BoxError> {
+    let GitHubUser { user, repositories } = get_github_user(gh_urls, github_token).await?;
 
     let header = Header::new(Algorithm::HS256);
 
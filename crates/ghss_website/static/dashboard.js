@@ -29,6 +29,37 @@ const timeRange = () => {
   return { start, end };
 };
 
+const showInactiveRepository = () => {
+  const dashboard = document.querySelector("#dashboard");
+  if (dashboard.hidden) {
+    return;
+  }
+
+  dashboard.hidden = true;
+  document.querySelector(".filters").hidden = true;
+  dashboard.before(
+    createElement("div", { className: "error" }, [
+      createElement("h2", { textContent: "Repository is inactive" }),
+      createElement("p", {
+        textContent:
+          "The repository was removed from the GitHub App or its installation was suspended.",
+      }),
+    ])
+  );
+};
+
+const fetchJson = async (url) => {
+  const res = await fetch(url.toString());
+  if (res.status === 404) {
+    showInactiveRepository();
+  }
+  if (!res.ok) {
+    throw new Error(`Query failed eith ${res.status} ${res.statusText}`);
+  }
+
+  return res.json();
+};
+
 const queryData = async ({ table, columns, groupBy, interval }) => {
   const time = timeRange();
 
@@ -43,14 +74,27 @@ const queryData = async ({ table, columns, groupBy, interval }) => {
   }
   if (interval) {
     url.searchParams.append("interval", interval);
+    url.searchParams.append(
+      "timezone",
+      Intl.DateTimeFormat().resolvedOptions().timeZone
+    );
   }
 
-  const res = await fetch(url.toString());
-  if (!res.ok) {
-    throw new Error(`Query failed eith ${res.status} ${res.statusText}`);
+  return fetchJson(url);
+};
+
+const queryFlakyBuilds = async ({ limit }) => {
+  const time = timeRange();
+
+  const url = new URL("/api/flaky", location);
+  url.searchParams.append("repository", repository);
+  url.searchParams.append("since", time.start);
+  url.searchParams.append("until", time.end);
+  if (limit) {
+    url.searchParams.append("limit", limit);
   }
 
-  return res.json();
+  return fetchJson(url);
 };
 
 const emptyData = () => {
@@ -346,11 +390,28 @@ const graphPanel = ({
   onTimeRangeChange(loadData);
 };
 
+// Names of the BuildOutcome values of the store.
+const outcomeNames = [
+  "Unknown",
+  "Pending",
+  "Success",
+  "Failure",
+  "Error",
+  "Cancelled",
+  "Neutral",
+  "Timed out",
+  "Action required",
+  "Skipped",
+  "Stale",
+  "Startup failure",
+];
+
 const tablePanel = ({
   title,
   query,
   values,
   labelColumnName,
+  labelFormat = (label) => label,
   elementSelector,
 }) => {
   const element = document.querySelector(elementSelector);
@@ -404,7 +465,7 @@ const tablePanel = ({
         createElement("tr", {}, [
           createElement("th", {
             scope: "row",
-            textContent: series.tags[0],
+            textContent: labelFormat(series.tags[0]),
           }),
           ...values.map((value, i) =>
             createElement("td", {
@@ -419,6 +480,75 @@ const tablePanel = ({
   onTimeRangeChange(loadData);
 };
 
+const flakyBuildsPanel = ({ title, limit, elementSelector }) => {
+  const element = document.querySelector(elementSelector);
+
+  const headingId = `panel-headline-${title
+    .toLowerCase()
+    .replace(/[^a-z]/g, "-")}`;
+  element.appendChild(
+    createElement("h2", {
+      id: headingId,
+      textContent: title,
+    })
+  );
+
+  const thead = createElement("thead", {}, [
+    createElement("tr", {}, [
+      createElement("th", { scope: "col", textContent: "Check" }),
+      createElement("th", { scope: "col", textContent: "Flakiness" }),
+      createElement("th", { scope: "col", textContent: "Flaky / total commits" }),
+      createElement("th", { scope: "col", textContent: "Flaky SHAs" }),
+    ]),
+  ]);
+
+  const tbody = createElement("tbody");
+
+  const table = createElement(
+    "table",
+    {
+      "aria-labelledby": headingId,
+      className: "table-stat",
+    },
+    [thead, tbody]
+  );
+
+  element.append(table);
+
+  const loadData = async () => {
+    const raw = await queryFlakyBuilds({ limit });
+
+    while (tbody.firstChild) {
+      tbody.removeChild(tbody.firstChild);
+    }
+
+    tbody.append(
+      ...raw.builds.map((build) =>
+        createElement("tr", {}, [
+          createElement("th", {
+            scope: "row",
+            textContent: build.name,
+          }),
+          createElement("td", {
+            textContent: `${formatNumber(build.flakiness * 100)}%`,
+          }),
+          createElement("td", {
+            textContent: `${build.flaky_commits.length} / ${build.commits}`,
+          }),
+          createElement("td", {
+            className: "commits",
+            textContent: build.flaky_commits
+              .map((commit) => commit.substring(0, 7))
+              .join(" "),
+          }),
+        ])
+      )
+    );
+  };
+  loadData();
+  onTimeRangeChange(loadData);
+};
+
 window.addEventListener("load", () => {
   statPanel({
     title: "Overall success rate",
@@ -456,7 +586,14 @@ window.addEventListener("load", () => {
     title: "Statistics by pipeline",
     query: {
       table: "builds",
-      columns: ["count(commit)", "avg(duration_ms)", "avg(successful)"],
+      columns: [
+        "count(commit)",
+        "avg(duration_ms)",
+        "p90(duration_ms)",
+        "sum(duration_ms)",
+        "avg(queue_ms)",
+        "avg(successful)",
+      ],
       groupBy: ["name"],
     },
     values: [
@@ -470,6 +607,21 @@ window.addEventListener("load", () => {
         transform: (value) => value / 1000 / 60,
         format: (value) => `${formatNumber(value)} min`,
       },
+      {
+        columnName: "Duration (p90)",
+        transform: (value) => value / 1000 / 60,
+        format: (value) => `${formatNumber(value)} min`,
+      },
+      {
+        columnName: "Total duration",
+        transform: (value) => value / 1000 / 60 / 60,
+        format: (value) => `${formatNumber(value)} h`,
+      },
+      {
+        columnName: "Queue time",
+        transform: (value) => value / 1000 / 60,
+        format: (value) => `${formatNumber(value)} min`,
+      },
       {
         columnName: "Success",
         transform: (value) => value * 100,
@@ -480,6 +632,25 @@ window.addEventListener("load", () => {
     elementSelector: "#stats-by-pipeline",
   });
 
+  tablePanel({
+    title: "Outcomes",
+    query: {
+      table: "builds",
+      columns: ["count(commit)"],
+      groupBy: ["outcome"],
+    },
+    values: [
+      {
+        columnName: "Count",
+        transform: (value) => value,
+        format: (value) => value,
+      },
+    ],
+    labelColumnName: "Outcome",
+    labelFormat: (outcome) => outcomeNames[outcome] || outcome,
+    elementSelector: "#outcomes",
+  });
+
   graphPanel({
     title: "Duration",
     height: 410,
@@ -507,4 +678,10 @@ window.addEventListener("load", () => {
     valueFormat: (value) => formatNumber(value),
     elementSelector: "#attempts",
   });
+
+  flakyBuildsPanel({
+    title: "Flakiest checks",
+    limit: 10,
+    elementSelector: "#flaky-builds",
+  });
 });